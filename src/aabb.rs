@@ -46,6 +46,11 @@ impl AABB {
         }
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn center(&self) -> Vec3 {
         0.5 * (self.min + self.max)
     }
@@ -1,5 +1,10 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::aabb::{Bounded, AABB};
-use crate::geometry::Sphere;
+use crate::geometry::{Sphere, Triangle, Volume};
 use crate::traits::AsBytes;
 use glam::Vec3;
 
@@ -8,6 +13,37 @@ use glam::Vec3;
 #[derive(Debug, Clone, Copy)]
 pub enum Leaf {
     S(Sphere),
+    T(Triangle),
+    V(Volume),
+}
+
+// Tags stored in the w component of the first vec4 of every element. Spheres
+// store their radius there instead.
+pub const NODE_TYPE: u32 = 0xFFFFFFFF;
+pub const TRIANGLE_TYPE: u32 = 0xFFFFFFFE;
+pub const VOLUME_TYPE: u32 = 0xFFFFFFFD;
+
+// Largest group of leaves tested one after the other under a single node
+const MAX_LEAF_SIZE: usize = 4;
+
+/// How the leaves are partitioned at each node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildStrategy {
+    /// Split at the median centroid along the largest axis.
+    Median,
+    /// Binned surface area heuristic. `leaf_cost` is the cost of intersecting
+    /// a primitive relative to a box test, small groups of leaves are not
+    /// split further when testing all of them is cheaper.
+    Sah { bins: usize, leaf_cost: f32 },
+}
+
+impl Default for BuildStrategy {
+    fn default() -> Self {
+        BuildStrategy::Sah {
+            bins: 16,
+            leaf_cost: 1.0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -21,6 +57,20 @@ pub struct Node {
 unsafe impl bytemuck::Pod for Node {}
 unsafe impl bytemuck::Zeroable for Node {}
 
+// GPU representation of triangle and volume leaves, their data lives in the
+// triangle or volume buffer at `index`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LeafRef {
+    pad0: Vec3,
+    leaf_type: u32,
+    index: u32,
+    pad1: [u32; 2],
+    esc_index: u32,
+}
+unsafe impl bytemuck::Pod for LeafRef {}
+unsafe impl bytemuck::Zeroable for LeafRef {}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub enum BVHElement {
@@ -36,20 +86,70 @@ pub struct BVH {
 }
 
 impl BVH {
-    pub fn from_spheres(objects: &[Sphere]) -> Self {
-        let mut leaves = Vec::with_capacity(objects.len());
-        for obj in objects {
-            leaves.push(Leaf::S(*obj));
+    pub fn from_primitives(
+        spheres: &[Sphere],
+        triangles: &[Triangle],
+        volumes: &[Volume],
+        strategy: BuildStrategy,
+    ) -> Self {
+        Self::new(leaves(spheres, triangles, volumes).as_slice(), strategy)
+    }
+
+    /// Same as `from_primitives` but reuses the tree stored in `cache_dir` by
+    /// a previous run when the leaves and settings are unchanged.
+    pub fn from_primitives_cached(
+        spheres: &[Sphere],
+        triangles: &[Triangle],
+        volumes: &[Volume],
+        strategy: BuildStrategy,
+        cache_dir: &Path,
+    ) -> Self {
+        let leaves = leaves(spheres, triangles, volumes);
+        let key = Self::cache_key(&leaves, strategy);
+        let path = cache_dir.join(format!("{:016x}.bvh", key));
+
+        match Self::load(&path, key) {
+            Ok(bvh) => return bvh,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("{}: ignoring BVH cache, {}", path.display(), e),
+        }
+
+        let bvh = Self::from_primitives(spheres, triangles, volumes, strategy);
+        if let Err(e) = fs::create_dir_all(cache_dir).and_then(|_| bvh.save(&path, key)) {
+            eprintln!("{}: could not write BVH cache, {}", path.display(), e);
         }
+        bvh
+    }
+
+    /// Triangles in the order they are referenced by the leaves, this is the
+    /// content of the triangle buffer.
+    pub fn triangles(&self) -> Vec<Triangle> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                BVHElement::Leaf(Leaf::T(t)) => Some(*t),
+                _ => None,
+            })
+            .collect()
+    }
 
-        Self::new(leaves.as_slice())
+    /// Volumes in the order they are referenced by the leaves, this is the
+    /// content of the volume buffer.
+    pub fn volumes(&self) -> Vec<Volume> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                BVHElement::Leaf(Leaf::V(v)) => Some(*v),
+                _ => None,
+            })
+            .collect()
     }
 
-    pub fn new(objects: &[Leaf]) -> Self {
+    pub fn new(objects: &[Leaf], strategy: BuildStrategy) -> Self {
         let mut index: Vec<usize> = (0..objects.len()).collect();
         let mut nodes: Vec<BVHElement> = Vec::with_capacity(objects.len() * 2);
 
-        Self::build_node(&mut nodes, objects, &mut index, 1);
+        Self::build_node(&mut nodes, objects, &mut index, 1, strategy);
 
         let nodes_len = nodes.len() as u32;
         for node in nodes.iter_mut() {
@@ -66,6 +166,7 @@ impl BVH {
         objects: &[Leaf],
         index: &mut Vec<usize>,
         esc_index: u32,
+        strategy: BuildStrategy,
     ) -> usize {
         if index.len() == 1 {
             nodes.push(objects[index[0]].as_element(esc_index));
@@ -77,19 +178,43 @@ impl BVH {
             bounds.extend(&objects[*i].get_bounds());
         }
 
-        let (mut li, mut ri) = Self::split(objects, index, &bounds);
+        let split = match strategy {
+            BuildStrategy::Median => Some(Self::split(objects, index, &bounds)),
+            BuildStrategy::Sah { bins, leaf_cost } => {
+                Self::split_sah(objects, index, &bounds, bins, leaf_cost)
+            }
+        };
 
         nodes.push(BVHElement::Node(Node {
             bb_min: bounds.min,
-            node_type: 0xFFFFFFFF,
+            node_type: NODE_TYPE,
             bb_max: bounds.max,
             esc_index: 0,
         }));
 
         let start_index = nodes.len() - 1;
 
-        let num_l = Self::build_node(nodes, objects, &mut li, esc_index + 1);
-        let num_r = Self::build_node(nodes, objects, &mut ri, esc_index + num_l as u32 + 1);
+        let (mut li, mut ri) = match split {
+            Some(split) => split,
+            None => {
+                // Group of leaves, each one escapes to the next and the last
+                // one out of the node
+                for (i, obj) in index.iter().enumerate() {
+                    nodes.push(objects[*obj].as_element(esc_index + 1 + i as u32));
+                }
+                nodes[start_index].set_esc_index((start_index + index.len() + 1) as u32);
+                return index.len() + 1;
+            }
+        };
+
+        let num_l = Self::build_node(nodes, objects, &mut li, esc_index + 1, strategy);
+        let num_r = Self::build_node(
+            nodes,
+            objects,
+            &mut ri,
+            esc_index + num_l as u32 + 1,
+            strategy,
+        );
 
         let e = (start_index + num_l + num_r + 1) as u32;
         nodes[start_index].set_esc_index(e);
@@ -119,6 +244,99 @@ impl BVH {
         let (l, r) = index.split_at(index.len() / 2);
         (l.to_vec(), r.to_vec())
     }
+
+    /// Binned SAH split, `None` when intersecting every leaf is cheaper than
+    /// splitting them.
+    fn split_sah(
+        objects: &[Leaf],
+        index: &mut Vec<usize>,
+        bounds: &AABB,
+        bins: usize,
+        leaf_cost: f32,
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let bins = bins.max(2);
+        let centers: Vec<[f32; 3]> = index
+            .iter()
+            .map(|i| objects[*i].get_bounds().center().into())
+            .collect();
+
+        let (mut cmin, mut cmax) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for c in centers.iter() {
+            for axis in 0..3 {
+                cmin[axis] = cmin[axis].min(c[axis]);
+                cmax[axis] = cmax[axis].max(c[axis]);
+            }
+        }
+        let bin = |c: &[f32; 3], axis: usize| {
+            let t = (c[axis] - cmin[axis]) / (cmax[axis] - cmin[axis]);
+            ((t * bins as f32) as usize).min(bins - 1)
+        };
+        let area = |b: &Option<AABB>| b.map_or(0.0, |b| b.surface_area());
+        let union = |a: Option<AABB>, b: Option<AABB>| match (a, b) {
+            (Some(a), Some(b)) => Some(AABB::union(&a, &b)),
+            (a, b) => a.or(b),
+        };
+
+        // (cost, axis, last bin on the left)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if cmax[axis] <= cmin[axis] {
+                continue;
+            }
+
+            let mut bin_bounds: Vec<Option<AABB>> = vec![None; bins];
+            let mut counts = vec![0; bins];
+            for (i, c) in index.iter().zip(centers.iter()) {
+                let b = bin(c, axis);
+                counts[b] += 1;
+                bin_bounds[b] = union(bin_bounds[b], Some(objects[*i].get_bounds()));
+            }
+
+            // Sweep from the right, then evaluate every plane from the left
+            let mut right_cost = vec![0.0; bins];
+            let (mut acc, mut n) = (None, 0);
+            for b in (1..bins).rev() {
+                acc = union(acc, bin_bounds[b]);
+                n += counts[b];
+                right_cost[b] = area(&acc) * n as f32;
+            }
+
+            let (mut acc, mut n) = (None, 0);
+            for b in 0..bins - 1 {
+                acc = union(acc, bin_bounds[b]);
+                n += counts[b];
+                if n == 0 || n == index.len() {
+                    continue;
+                }
+                let cost = area(&acc) * n as f32 + right_cost[b + 1];
+                if best.map_or(true, |(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        match best {
+            Some((cost, axis, last)) => {
+                let split_cost =
+                    1.0 + leaf_cost * cost / bounds.surface_area().max(f32::MIN_POSITIVE);
+                if index.len() <= MAX_LEAF_SIZE && leaf_cost * index.len() as f32 <= split_cost {
+                    return None;
+                }
+
+                let (mut l, mut r) = (Vec::new(), Vec::new());
+                for (i, c) in index.iter().zip(centers.iter()) {
+                    match bin(c, axis) <= last {
+                        true => l.push(*i),
+                        false => r.push(*i),
+                    }
+                }
+                Some((l, r))
+            }
+            // All centroids coincide
+            None if index.len() <= MAX_LEAF_SIZE => None,
+            None => Some(Self::split(objects, index, bounds)),
+        }
+    }
 }
 
 fn axis_size(v: Vec3, axis: Vec3) -> f32 {
@@ -135,6 +353,8 @@ impl Bounded for Leaf {
     fn get_bounds(&self) -> AABB {
         match self {
             Leaf::S(s) => s.get_bounds(),
+            Leaf::T(t) => t.get_bounds(),
+            Leaf::V(v) => v.get_bounds(),
         }
     }
 }
@@ -155,6 +375,8 @@ impl Leaf {
     fn as_element(&self, esc_index: u32) -> BVHElement {
         return match self {
             Leaf::S(s) => BVHElement::Leaf(Leaf::S(Sphere { esc_index, ..*s })),
+            Leaf::T(t) => BVHElement::Leaf(Leaf::T(Triangle { esc_index, ..*t })),
+            Leaf::V(v) => BVHElement::Leaf(Leaf::V(Volume { esc_index, ..*v })),
         };
     }
 }
@@ -165,6 +387,8 @@ impl BVHElement {
             BVHElement::Node(n) => n.esc_index = esc_index,
             BVHElement::Leaf(l) => match l {
                 Leaf::S(s) => s.esc_index = esc_index,
+                Leaf::T(t) => t.esc_index = esc_index,
+                Leaf::V(v) => v.esc_index = esc_index,
             },
         }
     }
@@ -174,20 +398,233 @@ impl BVHElement {
             BVHElement::Node(n) => n.esc_index,
             BVHElement::Leaf(l) => match l {
                 Leaf::S(s) => s.esc_index,
+                Leaf::T(t) => t.esc_index,
+                Leaf::V(v) => v.esc_index,
             },
         };
     }
 }
 
+fn leaves(spheres: &[Sphere], triangles: &[Triangle], volumes: &[Volume]) -> Vec<Leaf> {
+    let mut leaves = Vec::with_capacity(spheres.len() + triangles.len() + volumes.len());
+    for obj in spheres {
+        leaves.push(Leaf::S(*obj));
+    }
+    for obj in triangles {
+        leaves.push(Leaf::T(*obj));
+    }
+    for obj in volumes {
+        leaves.push(Leaf::V(*obj));
+    }
+    leaves
+}
+
+// ---- Cache ----
+// File layout, native endian:
+//   magic [u8; 8], version u32, padding u32, key u64, element count u64
+//   then per element a u32 tag followed by the Node, Sphere, Triangle or
+//   Volume.
+// Bump CACHE_VERSION whenever the layout or the builders change.
+
+const CACHE_MAGIC: [u8; 8] = *b"WGPURTBV";
+const CACHE_VERSION: u32 = 2;
+
+const TAG_NODE: u32 = 0;
+const TAG_SPHERE: u32 = 1;
+const TAG_TRIANGLE: u32 = 2;
+const TAG_VOLUME: u32 = 3;
+
+/// Default location of cached trees.
+pub fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join("wgpu-raytracer")
+}
+
+// 64 bit FNV-1a, unlike std's hasher it is stable between runs and releases
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl BVH {
+    /// Identifies the tree built from `objects` with `strategy`.
+    pub fn cache_key(objects: &[Leaf], strategy: BuildStrategy) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(&CACHE_VERSION.to_le_bytes());
+        match strategy {
+            BuildStrategy::Median => hash.write(&[0]),
+            BuildStrategy::Sah { bins, leaf_cost } => {
+                hash.write(&[1]);
+                hash.write(&(bins as u64).to_le_bytes());
+                hash.write(&leaf_cost.to_bits().to_le_bytes());
+            }
+        }
+
+        // Escape indices are an output of the build, leave them out
+        for obj in objects {
+            match obj.as_element(0) {
+                BVHElement::Leaf(Leaf::S(s)) => {
+                    hash.write(&[TAG_SPHERE as u8]);
+                    hash.write(bytemuck::bytes_of(&s));
+                }
+                BVHElement::Leaf(Leaf::T(t)) => {
+                    hash.write(&[TAG_TRIANGLE as u8]);
+                    hash.write(bytemuck::bytes_of(&t));
+                }
+                BVHElement::Leaf(Leaf::V(v)) => {
+                    hash.write(&[TAG_VOLUME as u8]);
+                    hash.write(bytemuck::bytes_of(&v));
+                }
+                BVHElement::Node(_) => unreachable!(),
+            }
+        }
+        hash.0
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, key: u64) -> io::Result<()> {
+        // Write next to the destination and rename so that an interrupted
+        // run never leaves a truncated cache behind. The temporary name is
+        // unique to this process and call, so concurrent saves don't collide.
+        static SAVES: AtomicUsize = AtomicUsize::new(0);
+        let path = path.as_ref();
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut w = io::BufWriter::new(fs::File::create(&tmp)?);
+        let written = self.write(&mut w, key).and_then(|_| w.flush());
+        drop(w);
+        match written {
+            Ok(()) => fs::rename(&tmp, path),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+
+    /// Fails with `InvalidData` when the file is not a cache for `key`.
+    pub fn load<P: AsRef<Path>>(path: P, key: u64) -> io::Result<Self> {
+        Self::read(&mut io::BufReader::new(fs::File::open(path)?), key)
+    }
+
+    fn write<W: Write>(&self, w: &mut W, key: u64) -> io::Result<()> {
+        w.write_all(&CACHE_MAGIC)?;
+        w.write_all(bytemuck::bytes_of(&[CACHE_VERSION, 0]))?;
+        w.write_all(bytemuck::bytes_of(&[key, self.nodes.len() as u64]))?;
+
+        for node in self.nodes.iter() {
+            match node {
+                BVHElement::Node(n) => {
+                    w.write_all(bytemuck::bytes_of(&TAG_NODE))?;
+                    w.write_all(bytemuck::bytes_of(n))?;
+                }
+                BVHElement::Leaf(Leaf::S(s)) => {
+                    w.write_all(bytemuck::bytes_of(&TAG_SPHERE))?;
+                    w.write_all(bytemuck::bytes_of(s))?;
+                }
+                BVHElement::Leaf(Leaf::T(t)) => {
+                    w.write_all(bytemuck::bytes_of(&TAG_TRIANGLE))?;
+                    w.write_all(bytemuck::bytes_of(t))?;
+                }
+                BVHElement::Leaf(Leaf::V(v)) => {
+                    w.write_all(bytemuck::bytes_of(&TAG_VOLUME))?;
+                    w.write_all(bytemuck::bytes_of(v))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R, key: u64) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if magic != CACHE_MAGIC {
+            return Err(invalid("not a BVH cache file"));
+        }
+        let [version, _] = read_pod::<[u32; 2], _>(r)?;
+        if version != CACHE_VERSION {
+            return Err(invalid("cache version mismatch"));
+        }
+        let [file_key, len] = read_pod::<[u64; 2], _>(r)?;
+        if file_key != key {
+            return Err(invalid("cache key mismatch"));
+        }
+
+        // Don't trust the length for the allocation, the file may be truncated
+        let mut nodes = Vec::with_capacity((len as usize).min(1 << 20));
+        for _ in 0..len {
+            let node = match read_pod::<u32, _>(r)? {
+                TAG_NODE => BVHElement::Node(read_pod(r)?),
+                TAG_SPHERE => BVHElement::Leaf(Leaf::S(read_pod(r)?)),
+                TAG_TRIANGLE => BVHElement::Leaf(Leaf::T(read_pod(r)?)),
+                TAG_VOLUME => BVHElement::Leaf(Leaf::V(read_pod(r)?)),
+                _ => return Err(invalid("unknown element tag")),
+            };
+            // Traversal only moves forward and must stay inside the tree
+            let esc = node.get_esc_index();
+            if esc != 0xFFFFFFFF && (esc as usize <= nodes.len() || esc as u64 >= len) {
+                return Err(invalid("escape index out of range"));
+            }
+            nodes.push(node);
+        }
+
+        let mut rest = [0u8; 1];
+        if r.read(&mut rest)? != 0 {
+            return Err(invalid("trailing data"));
+        }
+        Ok(BVH { nodes })
+    }
+}
+
+fn read_pod<T: bytemuck::Pod, R: Read>(r: &mut R) -> io::Result<T> {
+    let mut value = T::zeroed();
+    r.read_exact(bytemuck::bytes_of_mut(&mut value))?;
+    Ok(value)
+}
+
 impl AsBytes for BVH {
     fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
+        let mut tri_index = 0;
+        let mut vol_index = 0;
 
         for node in &self.nodes {
             match node {
                 BVHElement::Node(n) => bytes.extend_from_slice(bytemuck::bytes_of(n)),
                 BVHElement::Leaf(l) => match l {
                     Leaf::S(s) => bytes.extend_from_slice(bytemuck::bytes_of(s)),
+                    Leaf::T(t) => {
+                        bytes.extend_from_slice(bytemuck::bytes_of(&LeafRef {
+                            pad0: Vec3::zero(),
+                            leaf_type: TRIANGLE_TYPE,
+                            index: tri_index,
+                            pad1: [0; 2],
+                            esc_index: t.esc_index,
+                        }));
+                        tri_index += 1;
+                    }
+                    Leaf::V(v) => {
+                        bytes.extend_from_slice(bytemuck::bytes_of(&LeafRef {
+                            pad0: Vec3::zero(),
+                            leaf_type: VOLUME_TYPE,
+                            index: vol_index,
+                            pad1: [0; 2],
+                            esc_index: v.esc_index,
+                        }));
+                        vol_index += 1;
+                    }
                 },
             };
         }
@@ -203,6 +640,7 @@ impl AsBytes for BVH {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::geometry::NO_LIGHT;
 
     struct State {
         a: u32,
@@ -231,13 +669,164 @@ mod tests {
                 center: Vec3::new(rng(&mut s, 100.0), rng(&mut s, 100.0), rng(&mut s, 100.0)),
                 radius: 1.0,
                 mat_index: 1,
-                pad0: [0.0, 0.0],
+                light_index: NO_LIGHT,
+                pad0: 0.0,
                 esc_index: 0,
             }))
         }
         println!("Made spheres");
 
-        let bvh = BVH::new(objects.as_mut_slice());
+        let bvh = BVH::new(objects.as_mut_slice(), BuildStrategy::Median);
         println!("{:?}", bvh);
     }
+
+    #[test]
+    fn test_mixed_leaves() {
+        let mut s = State { a: 35924 };
+        let mut spheres = Vec::new();
+        let mut triangles = Vec::new();
+        let mut volumes = Vec::new();
+        for _ in 0..100 {
+            let p = Vec3::new(rng(&mut s, 10.0), rng(&mut s, 10.0), rng(&mut s, 10.0));
+            spheres.push(Sphere::new(p, 0.5, 0));
+            triangles.push(Triangle::new(
+                [p, p + Vec3::unit_x(), p + Vec3::unit_z()],
+                1,
+            ));
+            volumes.push(Volume::new(p, p + Vec3::one(), 0, 2));
+        }
+
+        let bvh = BVH::from_primitives(&spheres, &triangles, &volumes, BuildStrategy::Median);
+        assert_eq!(bvh.nodes.len(), 2 * 300 - 1);
+        assert_eq!(bvh.triangles().len(), 100);
+        assert_eq!(bvh.volumes().len(), 100);
+        assert_eq!(bvh.as_bytes().len(), 32 * bvh.nodes.len());
+
+        // Triangle and volume leaves reference their buffers in traversal
+        // order
+        let bytes = bvh.as_bytes();
+        let words: &[u32] = bytemuck::cast_slice(&bytes);
+        let mut expected = [0, 0];
+        for element in words.chunks(8) {
+            let buffer = match element[3] {
+                TRIANGLE_TYPE => 0,
+                VOLUME_TYPE => 1,
+                _ => continue,
+            };
+            assert_eq!(element[4], expected[buffer]);
+            expected[buffer] += 1;
+        }
+        assert_eq!(expected, [100, 100]);
+
+        // Escape indices either point inside the tree or end the traversal
+        for node in bvh.nodes.iter() {
+            let e = node.get_esc_index();
+            assert!(e == 0xFFFFFFFF || (e as usize) < bvh.nodes.len());
+        }
+    }
+
+    /// Expected number of box and primitive tests for a random ray hitting
+    /// the root.
+    fn traversal_cost(bvh: &BVH) -> f32 {
+        let len = bvh.nodes.len() as u32;
+        let end = |e: u32| if e == 0xFFFFFFFF { len } else { e };
+        let root_area = bvh.nodes[0].get_bounds().surface_area();
+
+        let mut cost = 1.0;
+        for (i, node) in bvh.nodes.iter().enumerate() {
+            if let BVHElement::Node(_) = node {
+                let p = node.get_bounds().surface_area() / root_area;
+                let mut child = i as u32 + 1;
+                while child < end(node.get_esc_index()) {
+                    cost += p;
+                    child = end(bvh.nodes[child as usize].get_esc_index());
+                }
+            }
+        }
+        cost
+    }
+
+    #[test]
+    fn test_sah() {
+        let scene = crate::scene::Scene::load("scenes/default.ron").unwrap();
+        let median = BVH::from_primitives(&scene.spheres, &[], &[], BuildStrategy::Median);
+        let sah = BVH::from_primitives(&scene.spheres, &[], &[], BuildStrategy::default());
+
+        // Every leaf is in the tree once
+        let mut radii: Vec<u32> = sah
+            .nodes
+            .iter()
+            .filter_map(|n| match n {
+                BVHElement::Leaf(Leaf::S(s)) => Some(s.radius.to_bits()),
+                _ => None,
+            })
+            .collect();
+        let mut expected: Vec<u32> = scene.spheres.iter().map(|s| s.radius.to_bits()).collect();
+        radii.sort();
+        expected.sort();
+        assert_eq!(radii, expected);
+        for node in sah.nodes.iter() {
+            let e = node.get_esc_index();
+            assert!(e == 0xFFFFFFFF || (e as usize) < sah.nodes.len());
+        }
+
+        // The huge ground sphere makes the median split a poor tree
+        assert!(traversal_cost(&sah) < 0.5 * traversal_cost(&median));
+    }
+
+    #[test]
+    fn test_cache() {
+        let scene = crate::scene::Scene::load("scenes/cornell.ron").unwrap();
+        let objects = leaves(&scene.spheres, &scene.triangles, &scene.volumes);
+        let strategy = BuildStrategy::default();
+        let key = BVH::cache_key(&objects, strategy);
+        let bvh = BVH::new(&objects, strategy);
+
+        let dir = std::env::temp_dir().join(format!("bvh-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.bvh");
+        // Concurrent saves each write their own temporary file
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| bvh.save(&path, key).unwrap());
+            }
+        });
+        let loaded = BVH::load(&path, key);
+        let wrong_key = BVH::load(&path, key + 1);
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap().as_bytes(), bvh.as_bytes());
+        assert_eq!(wrong_key.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(files, 1);
+
+        // Any change to the leaves or the settings gives a new key
+        let mut moved = objects.clone();
+        match &mut moved[0] {
+            Leaf::S(s) => s.center += Vec3::new(0.0, 1e-3, 0.0),
+            Leaf::T(t) => t.vertices[0] += Vec3::new(0.0, 1e-3, 0.0),
+            Leaf::V(v) => v.min += Vec3::new(0.0, 1e-3, 0.0),
+        }
+        assert_ne!(BVH::cache_key(&moved, strategy), key);
+        assert_ne!(BVH::cache_key(&objects, BuildStrategy::Median), key);
+        assert_eq!(BVH::cache_key(&objects, strategy), key);
+
+        // Truncated files are rejected
+        let mut bytes = Vec::new();
+        bvh.write(&mut bytes, key).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(BVH::read(&mut bytes.as_slice(), key).is_err());
+
+        // So are escape indices that loop back or leave the tree
+        for &esc in [0, bvh.nodes.len() as u32].iter() {
+            let mut broken = BVH {
+                nodes: bvh.nodes.clone(),
+            };
+            broken.nodes[0].set_esc_index(esc);
+            let mut bytes = Vec::new();
+            broken.write(&mut bytes, key).unwrap();
+            let err = BVH::read(&mut bytes.as_slice(), key).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
 }
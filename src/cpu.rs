@@ -0,0 +1,2716 @@
+use glam::{Vec2, Vec3};
+
+use crate::bvh::{NODE_TYPE, TRIANGLE_TYPE, VOLUME_TYPE};
+use crate::geometry::{MAX_TRACKING_STEPS, NO_LIGHT};
+use crate::globals::Globals;
+use crate::scene::Scene;
+use crate::texture::{Grid, NO_TEXTURE};
+use crate::traits::AsBytes;
+
+// CPU port of shader.comp. It reads the exact buffers uploaded to the GPU so
+// it can stand in for it when no adapter is available and check it in tests.
+// Keep it in sync with the GLSL.
+
+const TRIANGLE_STRIDE: usize = 8;
+const END_OF_TREE: u32 = 0xFFFFFFFF;
+
+const TEXTURE_IMAGE: u32 = 0;
+const TEXTURE_IMAGE_SRGB: u32 = 1;
+const TEXTURE_CHECKER: u32 = 2;
+const TEXTURE_NOISE: u32 = 3;
+const TEXTURE_MARBLE: u32 = 4;
+
+const ENVIRONMENT_HEADER: usize = 12;
+
+const SAMPLER_HALTON: u32 = 1;
+const SAMPLER_SOBOL: u32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + t * self.direction
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HitRec {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub t: f32,
+    pub front_face: bool,
+    pub mat_ptr: u32,
+    pub uv: Vec2,
+    /// Index of the volume whose box was hit, `None` for surfaces.
+    pub volume: Option<u32>,
+    /// Index of the light of emissive spheres, `None` for everything else.
+    pub light: Option<u32>,
+    /// Derivatives of the point with respect to `uv`.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Material {
+    albedo: Vec3,
+    metallic: f32,
+    emission: Vec3,
+    roughness: f32,
+    absorption: Vec3,
+    ior: f32,
+    albedo_texture: u32,
+    roughness_texture: u32,
+    emission_texture: u32,
+    specular: f32,
+    transmission: f32,
+    clearcoat: f32,
+    clearcoat_gloss: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    scattering: Vec3,
+    anisotropy: f32,
+    normal_texture: u32,
+    bump_texture: u32,
+    bump_scale: f32,
+    normal_scale: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Medium {
+    absorption: Vec3,
+    scattering: Vec3,
+    anisotropy: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Volume {
+    min: Vec3,
+    mat_ptr: u32,
+    max: Vec3,
+    density: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Light {
+    center: Vec3,
+    radius: f32,
+}
+
+pub struct Tracer {
+    globals: Globals,
+    bvh: Vec<u32>,
+    triangles: Vec<u32>,
+    materials: Vec<Material>,
+    textures: Vec<u32>,
+    volumes: Vec<Volume>,
+    lights: Vec<Light>,
+    environment: Vec<u32>,
+    grids: Grid,
+}
+
+impl Tracer {
+    /// Takes the contents of the BVH, triangle, material, texture, volume,
+    /// light and environment buffers as produced by `AsBytes`, and the grid
+    /// atlas uploaded as the grid texture.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        globals: Globals,
+        bvh: &[u8],
+        triangles: &[u8],
+        materials: &[u8],
+        textures: &[u8],
+        volumes: &[u8],
+        lights: &[u8],
+        environment: &[u8],
+        grids: &Grid,
+    ) -> Self {
+        let materials = words(materials)
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(32)
+            .map(|m| Material {
+                albedo: vec3(&m[0..3]),
+                metallic: f32::from_bits(m[3]),
+                emission: vec3(&m[4..7]),
+                roughness: f32::from_bits(m[7]),
+                absorption: vec3(&m[8..11]),
+                ior: f32::from_bits(m[11]),
+                albedo_texture: m[12],
+                roughness_texture: m[13],
+                emission_texture: m[14],
+                specular: f32::from_bits(m[15]),
+                transmission: f32::from_bits(m[16]),
+                clearcoat: f32::from_bits(m[17]),
+                clearcoat_gloss: f32::from_bits(m[18]),
+                sheen: f32::from_bits(m[19]),
+                sheen_tint: f32::from_bits(m[20]),
+                scattering: vec3(&m[24..27]),
+                anisotropy: f32::from_bits(m[27]),
+                normal_texture: m[28],
+                bump_texture: m[29],
+                bump_scale: f32::from_bits(m[30]),
+                normal_scale: f32::from_bits(m[31]),
+            })
+            .collect();
+        let volumes = words(volumes)
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(8)
+            .map(|v| Volume {
+                min: vec3(&v[0..3]),
+                mat_ptr: v[3],
+                max: vec3(&v[4..7]),
+                density: v[7],
+            })
+            .collect();
+        let lights = words(lights)
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(8)
+            .map(|l| Light {
+                center: vec3(&l[0..3]),
+                radius: f32::from_bits(l[3]),
+            })
+            .collect();
+
+        Tracer {
+            globals,
+            bvh: words(bvh),
+            triangles: words(triangles).get(4..).unwrap_or_default().to_vec(),
+            materials,
+            textures: words(textures),
+            volumes,
+            lights,
+            environment: words(environment),
+            grids: grids.clone(),
+        }
+    }
+
+    pub fn from_scene(scene: &Scene, width: u32, height: u32) -> Self {
+        let bvh = scene.build_bvh();
+        Tracer::new(
+            scene.globals(width, height),
+            &bvh.as_bytes(),
+            &bvh.triangles().as_bytes(),
+            &scene.materials.as_bytes(),
+            &scene.textures.as_bytes(),
+            &bvh.volumes().as_bytes(),
+            &scene.lights().as_bytes(),
+            &scene.environment.as_bytes(),
+            &Grid::atlas(&scene.textures),
+        )
+    }
+
+    /// Averages `samples` frames of the whole image, rows are split between
+    /// all available cores.
+    pub fn render(&self, samples: u32) -> Vec<[f32; 3]> {
+        let width = self.globals.window_size.x() as usize;
+        let height = self.globals.window_size.y() as usize;
+        let mut pixels = vec![[0.0; 3]; width * height];
+        if pixels.is_empty() {
+            return pixels;
+        }
+
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let rows = height.div_ceil(threads);
+        let scale = 1.0 / samples.max(1) as f32;
+
+        std::thread::scope(|s| {
+            for (i, chunk) in pixels.chunks_mut(rows * width).enumerate() {
+                s.spawn(move || {
+                    for (j, pixel) in chunk.iter_mut().enumerate() {
+                        let index = i * rows * width + j;
+                        let (x, y) = ((index % width) as u32, (index / width) as u32);
+                        let mut color = Vec3::zero();
+                        for frame in 0..samples {
+                            color += self.sample(x, y, frame);
+                        }
+                        *pixel = (color * scale).into();
+                    }
+                });
+            }
+        });
+
+        pixels
+    }
+
+    /// One invocation of the compute shader's `main` for `frame`.
+    pub fn sample(&self, x: u32, y: u32, frame: u32) -> Vec3 {
+        let g = &self.globals;
+        let pixel = Vec2::new(x as f32, y as f32);
+        let mut rng = Sampler::new(g.sampler_kind, [x, y], frame);
+
+        let sample_pos = (pixel + 2.0 * rng.next_2d() - Vec2::one()) / g.window_size;
+
+        // Camera
+        let theta = g.vfov.to_radians();
+        let h = (theta / 2.0).tan();
+        let viewport_height = 2.0 * h;
+        let viewport_width = g.aspect_ratio * viewport_height;
+
+        let vup = Vec3::unit_y();
+
+        let w = (g.look_from - g.look_at).normalize();
+        let u = vup.cross(w).normalize();
+        let v = w.cross(u);
+        let horizontal = g.focus_dist * viewport_width * u;
+        let vertical = g.focus_dist * viewport_height * v;
+        let upper_left_corner = g.look_from - 0.5 * horizontal + 0.5 * vertical - g.focus_dist * w;
+
+        let rd = 0.5 * g.aperture * random_in_unit_disk(rng.next_2d());
+        let offset = u * rd.x() + v * rd.y();
+
+        // Shoot ray
+        let ray_dir = (upper_left_corner + sample_pos.x() * horizontal
+            - sample_pos.y() * vertical
+            - g.look_from
+            - offset)
+            .normalize();
+        let r = Ray {
+            origin: g.look_from + offset,
+            direction: ray_dir,
+        };
+
+        self.ray_color(r, &mut rng)
+    }
+
+    pub fn ray_color(&self, mut r: Ray, rng: &mut Sampler) -> Vec3 {
+        let mut depth = 0;
+
+        let mut res = Vec3::zero();
+        let mut throughput = Vec3::one();
+        // Surface point and density of the last BSDF sample, zero after
+        // camera rays, media and delta pass-through where no light was sampled
+        let mut bounce_origin = r.origin;
+        let mut bounce_pdf = 0.0;
+        // Volume box the ray is in, surfaces inside it are surrounded by its
+        // medium too
+        let mut volume = self.volume_at(r.origin);
+
+        while depth < self.globals.max_depth {
+            let hit = self.hit_world(&r, 0.001, f32::MAX);
+            let (medium, in_volume) = hit.map_or((self.global_fog(), false), |rec| {
+                self.segment_medium(&rec, volume)
+            });
+
+            // Both take the same dimensions so later draws stay aligned
+            let s = rng.next_2d();
+            let t_max = hit.map_or(f32::MAX, |rec| rec.t);
+            let scattered = match volume {
+                Some(v) if in_volume => {
+                    self.sample_volume(v, &medium, &r, t_max, s, rng, &mut throughput)
+                }
+                _ => sample_medium(&medium, t_max, s, &mut throughput),
+            };
+            if throughput == Vec3::zero() {
+                break;
+            }
+            if let Some(t) = scattered {
+                depth += 1;
+                if !self.roulette(depth, rng.next_1d(), &mut throughput) {
+                    break;
+                }
+                r.origin = r.at(t);
+                r.direction = sample_hg(r.direction, medium.anisotropy, rng.next_2d());
+                bounce_pdf = 0.0;
+                continue;
+            }
+            let mut rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    res += self.escaped_radiance(r.direction, bounce_pdf) * throughput;
+                    break;
+                }
+            };
+
+            // Volume boxes only delimit their medium
+            if rec.volume.is_some() {
+                volume = rec.volume.filter(|_| rec.front_face);
+                r.origin = rec.point;
+                continue;
+            }
+
+            let mut material = self.material(rec.mat_ptr);
+
+            let mut emission = material.emission
+                * self.sample_texture(material.emission_texture, rec.uv, rec.point);
+            if let Some(light) = rec.light {
+                emission *= self.light_hit_weight(light, bounce_origin, bounce_pdf);
+            }
+            res += emission * throughput;
+
+            material.albedo *= self.sample_texture(material.albedo_texture, rec.uv, rec.point);
+            material.roughness *= self
+                .sample_texture(material.roughness_texture, rec.uv, rec.point)
+                .y();
+            rec.normal = self.perturb_normal(&material, &rec);
+
+            res += self.sample_direct(&material, &rec, -r.direction, volume, rng) * throughput;
+
+            let sample = bsdf_sample(&material, &rec, -r.direction, rng.next_3d());
+
+            depth += 1;
+
+            // Black surfaces, e.g. pure emitters, end the path
+            let (f, wi, pdf) = match sample {
+                Some((f, wi, pdf)) if pdf > 0.0 && f != Vec3::zero() => (f, wi, pdf),
+                _ => break,
+            };
+            throughput *= f * wi.dot(rec.normal).abs() / pdf;
+            if !self.roulette(depth, rng.next_1d(), &mut throughput) {
+                break;
+            }
+
+            // Only index matched surfaces pass light to their back side unbent
+            let pass_through = material.ior == 1.0 && wi.dot(rec.normal) < 0.0;
+            bounce_origin = rec.point;
+            bounce_pdf = if pass_through { 0.0 } else { pdf };
+
+            r.origin = rec.point;
+            r.direction = wi;
+        }
+
+        res
+    }
+
+    /// See `roulette` in shader.comp.
+    fn roulette(&self, depth: u32, s: f32, throughput: &mut Vec3) -> bool {
+        if depth < self.globals.roulette_depth {
+            return true;
+        }
+        let q = throughput.max_element().min(0.95);
+        if s >= q {
+            return false;
+        }
+        *throughput /= q;
+        true
+    }
+
+    /// Stackless traversal of the BVH buffer, see `hit_world` in
+    /// intersection.glsl.
+    pub fn hit_world(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRec> {
+        let mut rec = None;
+        let mut closest_so_far = t_max;
+        let inv_dir = Vec3::one() / r.direction;
+
+        let mut node_index = 0;
+        while node_index != END_OF_TREE {
+            let node = &self.bvh[8 * node_index as usize..8 * node_index as usize + 8];
+            let shape_type = node[3];
+
+            let hit = if shape_type == TRIANGLE_TYPE {
+                hit_triangle(&self.load_triangle(node[4]), r, t_min, closest_so_far)
+            } else if shape_type == VOLUME_TYPE {
+                self.hit_volume(node[4], r, inv_dir, t_min, closest_so_far)
+            } else if shape_type != NODE_TYPE {
+                let s = Sphere {
+                    center: vec3(&node[0..3]),
+                    radius: f32::from_bits(node[3]),
+                    mat_ptr: node[4],
+                    light: node[5],
+                };
+                hit_sphere(&s, r, t_min, closest_so_far)
+            } else if hit_box(vec3(&node[0..3]), vec3(&node[4..7]), r, inv_dir) {
+                node_index += 1;
+                continue;
+            } else {
+                None
+            };
+
+            if let Some(temp_rec) = hit {
+                closest_so_far = temp_rec.t;
+                rec = Some(temp_rec);
+            }
+
+            node_index = node[7];
+        }
+
+        rec
+    }
+
+    fn load_triangle(&self, index: u32) -> Triangle {
+        let base = 4 * TRIANGLE_STRIDE * index as usize;
+        let data = &self.triangles[base..base + 4 * TRIANGLE_STRIDE];
+        let row = |i: usize| vec3(&data[4 * i..4 * i + 3]);
+        Triangle {
+            v: [row(0), row(1), row(2)],
+            n: [row(3), row(4), row(5)],
+            uv: [
+                Vec2::new(f32::from_bits(data[24]), f32::from_bits(data[25])),
+                Vec2::new(f32::from_bits(data[26]), f32::from_bits(data[27])),
+                Vec2::new(f32::from_bits(data[28]), f32::from_bits(data[29])),
+            ],
+            mat_ptr: data[3],
+        }
+    }
+
+    /// See `sample_texture` in texture.glsl.
+    fn sample_texture(&self, index: u32, uv: Vec2, p: Vec3) -> Vec3 {
+        if index == NO_TEXTURE {
+            return Vec3::one();
+        }
+
+        let info = &self.textures[4 + 4 * index as usize..8 + 4 * index as usize];
+        let (kind, offset) = (info[0], info[1] as usize);
+
+        if kind == TEXTURE_IMAGE || kind == TEXTURE_IMAGE_SRGB {
+            return self.sample_image(offset, info[2], info[3], kind == TEXTURE_IMAGE_SRGB, uv);
+        }
+
+        let param = |i: usize| f32::from_bits(self.textures[offset + i]);
+        let a = Vec3::new(param(0), param(1), param(2));
+        let b = Vec3::new(param(3), param(4), param(5));
+        let scale = param(6);
+
+        let t = match kind {
+            TEXTURE_CHECKER => {
+                let cell = (p * scale + Vec3::splat(0.5)).floor();
+                (cell.x() + cell.y() + cell.z()).rem_euclid(2.0)
+            }
+            TEXTURE_NOISE => 0.5 * (1.0 + fbm(p * scale, self.textures[offset + 7])),
+            TEXTURE_MARBLE => {
+                let octaves = self.textures[offset + 7];
+                0.5 * (1.0 + (scale * p.z() + 10.0 * turbulence(p * scale, octaves)).sin())
+            }
+            _ => {
+                let start = Vec3::new(param(6), param(7), param(8));
+                let d = Vec3::new(param(9), param(10), param(11)) - start;
+                if d.dot(d) > 0.0 {
+                    (p - start).dot(d) / d.dot(d)
+                } else {
+                    0.0
+                }
+            }
+        };
+        a.lerp(b, t.clamp(0.0, 1.0))
+    }
+
+    /// See `perturb_normal` in texture.glsl.
+    fn perturb_normal(&self, mat: &Material, rec: &HitRec) -> Vec3 {
+        if mat.normal_texture == NO_TEXTURE && mat.bump_texture == NO_TEXTURE {
+            return rec.normal;
+        }
+
+        let side = if rec.front_face { 1.0 } else { -1.0 };
+        let mut n = side * rec.normal;
+
+        let t = rec.dpdu - n * n.dot(rec.dpdu);
+        if mat.normal_texture != NO_TEXTURE && t.dot(t) > 0.0 {
+            // Texture up is along decreasing v
+            let t = t.normalize();
+            let mut b = n.cross(t);
+            if b.dot(rec.dpdv) > 0.0 {
+                b = -b;
+            }
+            let m = 2.0 * self.sample_texture(mat.normal_texture, rec.uv, rec.point) - Vec3::one();
+            n = (mat.normal_scale * (m.x() * t + m.y() * b) + m.z() * n).normalize();
+        }
+
+        if mat.bump_texture != NO_TEXTURE {
+            let height = |uv: Vec2, p: Vec3| self.sample_texture(mat.bump_texture, uv, p).y();
+            let h = height(rec.uv, rec.point);
+            let hu = height(
+                rec.uv + Vec2::new(BUMP_DELTA, 0.0),
+                rec.point + BUMP_DELTA * rec.dpdu,
+            );
+            let hv = height(
+                rec.uv + Vec2::new(0.0, BUMP_DELTA),
+                rec.point + BUMP_DELTA * rec.dpdv,
+            );
+            let dpdu = rec.dpdu + mat.bump_scale * (hu - h) / BUMP_DELTA * n;
+            let dpdv = rec.dpdv + mat.bump_scale * (hv - h) / BUMP_DELTA * n;
+            let bumped = dpdu.cross(dpdv);
+            if bumped.dot(bumped) > 0.0 {
+                let side = if bumped.dot(n) < 0.0 { -1.0 } else { 1.0 };
+                n = bumped.normalize() * side;
+            }
+        }
+
+        side * n
+    }
+
+    fn sample_image(&self, offset: usize, width: u32, height: u32, srgb: bool, uv: Vec2) -> Vec3 {
+        let size = Vec2::new(width as f32, height as f32);
+        let p = uv * size - Vec2::splat(0.5);
+        let p0 = p.floor();
+        let f = p - p0;
+        let p0 = Vec2::new(p0.x().rem_euclid(size.x()), p0.y().rem_euclid(size.y()));
+        let p1 = Vec2::new(
+            (p0.x() + 1.0).rem_euclid(size.x()),
+            (p0.y() + 1.0).rem_euclid(size.y()),
+        );
+
+        let texel = |x: f32, y: f32| {
+            let c = self.textures[offset + y as usize * width as usize + x as usize];
+            let c = Vec3::new(
+                (c & 0xFF) as f32 / 255.0,
+                (c >> 8 & 0xFF) as f32 / 255.0,
+                (c >> 16 & 0xFF) as f32 / 255.0,
+            );
+            match srgb {
+                true => Vec3::new(
+                    srgb_to_linear(c.x()),
+                    srgb_to_linear(c.y()),
+                    srgb_to_linear(c.z()),
+                ),
+                false => c,
+            }
+        };
+        let c00 = texel(p0.x(), p0.y());
+        let c10 = texel(p1.x(), p0.y());
+        let c01 = texel(p0.x(), p1.y());
+        let c11 = texel(p1.x(), p1.y());
+        let top = c00.lerp(c10, f.x());
+        let bottom = c01.lerp(c11, f.x());
+        top.lerp(bottom, f.y())
+    }
+
+    /// See `hit_volume` in intersection.glsl.
+    fn hit_volume(
+        &self,
+        index: u32,
+        r: &Ray,
+        inv_dir: Vec3,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRec> {
+        let v = &self.volumes[index as usize];
+        let tbot = inv_dir * (v.min - r.origin);
+        let ttop = inv_dir * (v.max - r.origin);
+        let tmin = ttop.min(tbot);
+        let tmax = ttop.max(tbot);
+        let t0 = tmin.x().max(tmin.y()).max(tmin.z());
+        let t1 = tmax.x().min(tmax.y()).min(tmax.z());
+        if t1 < t0 {
+            return None;
+        }
+
+        let t = if t0 >= t_min { t0 } else { t1 };
+        if t > t_max || t < t_min {
+            return None;
+        }
+
+        Some(HitRec {
+            point: r.at(t),
+            normal: -r.direction,
+            t,
+            front_face: t == t0,
+            mat_ptr: v.mat_ptr,
+            uv: Vec2::zero(),
+            volume: Some(index),
+            light: None,
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+        })
+    }
+
+    fn grid_majorant(&self, index: u32) -> f32 {
+        let offset = self.textures[4 + 4 * index as usize + 1] as usize;
+        f32::from_bits(self.textures[offset + 2])
+    }
+
+    /// See `sample_grid` in texture.glsl, the lookup filters the atlas like
+    /// the linear sampler.
+    fn sample_grid(&self, index: u32, uvw: Vec3) -> f32 {
+        let info = &self.textures[4 + 4 * index as usize..8 + 4 * index as usize];
+        let offset = info[1] as usize;
+        let size = [info[2], info[3], self.textures[offset]];
+        let z0 = self.textures[offset + 1] as usize;
+        let sizef = Vec3::new(size[0] as f32, size[1] as f32, size[2] as f32);
+        let p = (uvw * sizef)
+            .max(Vec3::splat(0.5))
+            .min(sizef - Vec3::splat(0.5))
+            - Vec3::splat(0.5);
+        let v = p.floor();
+        let f = p - v;
+
+        let atlas = &self.grids;
+        let voxel = |dx: i32, dy: i32, dz: i32| {
+            let c = |i: usize, d: i32| (v[i] as i32 + d).clamp(0, size[i] as i32 - 1) as usize;
+            let (x, y, z) = (c(0, dx), c(1, dy), c(2, dz) + z0);
+            atlas.values[(z * atlas.height as usize + y) * atlas.width as usize + x]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(voxel(0, 0, 0), voxel(1, 0, 0), f.x());
+        let c10 = lerp(voxel(0, 1, 0), voxel(1, 1, 0), f.x());
+        let c01 = lerp(voxel(0, 0, 1), voxel(1, 0, 1), f.x());
+        let c11 = lerp(voxel(0, 1, 1), voxel(1, 1, 1), f.x());
+        lerp(lerp(c00, c10, f.y()), lerp(c01, c11, f.y()), f.z())
+    }
+
+    fn material(&self, index: u32) -> Material {
+        self.materials[index as usize]
+    }
+}
+
+struct Sphere {
+    center: Vec3,
+    radius: f32,
+    mat_ptr: u32,
+    light: u32,
+}
+
+struct Triangle {
+    v: [Vec3; 3],
+    n: [Vec3; 3],
+    uv: [Vec2; 3],
+    mat_ptr: u32,
+}
+
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect()
+}
+
+fn vec3(words: &[u32]) -> Vec3 {
+    Vec3::new(
+        f32::from_bits(words[0]),
+        f32::from_bits(words[1]),
+        f32::from_bits(words[2]),
+    )
+}
+
+// ---- texture.glsl ----
+
+// Step in texture space of the finite differences of bump maps
+const BUMP_DELTA: f32 = 1e-3;
+
+fn perlin(p: Vec3) -> f32 {
+    let i = p.floor();
+    let f = p - i;
+    let w = f * f * f * (f * (f * 6.0 - Vec3::splat(15.0)) + Vec3::splat(10.0));
+
+    let mut n = 0.0;
+    for c in 0..8 {
+        let corner = Vec3::new((c & 1) as f32, (c >> 1 & 1) as f32, (c >> 2 & 1) as f32);
+        let lattice = i + corner;
+        let g =
+            2.0 * hash3([
+                lattice.x() as i32 as u32,
+                lattice.y() as i32 as u32,
+                lattice.z() as i32 as u32,
+            ]) - Vec3::one();
+        let weight = (Vec3::one() - w) * (Vec3::one() - corner) + w * corner;
+        n += weight.x() * weight.y() * weight.z() * g.dot(f - corner);
+    }
+    n
+}
+
+fn fbm(mut p: Vec3, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p);
+        p *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum
+}
+
+fn turbulence(mut p: Vec3, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p).abs();
+        p *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// ---- bsdf.glsl ----
+
+const MIN_ALPHA: f32 = 0.001;
+const PI: f32 = std::f32::consts::PI;
+
+fn luminance(c: Vec3) -> f32 {
+    c.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    if eta == 1.0 {
+        return 0.0;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+fn onb(n: Vec3) -> (Vec3, Vec3) {
+    let s = if n.z() >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (s + n.z());
+    let c = n.x() * n.y() * a;
+    (
+        Vec3::new(1.0 + s * n.x() * n.x() * a, s * c, -s * n.x()),
+        Vec3::new(c, s + n.y() * n.y() * a, -n.y()),
+    )
+}
+
+fn ggx_d(h: Vec3, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = h.x() * h.x() + h.y() * h.y() + a2 * h.z() * h.z();
+    a2 / (PI * d * d)
+}
+
+fn smith_lambda(v: Vec3, alpha: f32) -> f32 {
+    let tan2 = (v.x() * v.x() + v.y() * v.y()) / (v.z() * v.z());
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+fn smith_g1(v: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(v, alpha))
+}
+
+fn smith_g2(v: Vec3, l: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(v, alpha) + smith_lambda(l, alpha))
+}
+
+fn sample_ggx_vndf(v: Vec3, alpha: f32, s: Vec2) -> Vec3 {
+    let vh = Vec3::new(alpha * v.x(), alpha * v.y(), v.z()).normalize();
+    let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if len2 > 0.0 {
+        Vec3::new(-vh.y(), vh.x(), 0.0) / len2.sqrt()
+    } else {
+        Vec3::unit_x()
+    };
+    let t2 = vh.cross(t1);
+
+    let r = s.x().sqrt();
+    let phi = 2.0 * PI * s.y();
+    let p1 = r * phi.cos();
+    let p2 = r * phi.sin();
+    let blend = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - blend) * (1.0 - p1 * p1).sqrt() + blend * p2;
+
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    Vec3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.0)).normalize()
+}
+
+fn ggx_vndf_pdf(v: Vec3, h: Vec3, alpha: f32) -> f32 {
+    smith_g1(v, alpha) * v.dot(h).max(0.0) * ggx_d(h, alpha) / v.z()
+}
+
+fn gtr1_d(h: Vec3, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * h.z() * h.z()))
+}
+
+fn sample_gtr1(alpha: f32, s: Vec2) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - s.x())) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * s.y();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn sample_cosine_hemisphere(s: Vec2) -> Vec3 {
+    let r = s.x().sqrt();
+    let phi = 2.0 * PI * s.y();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - s.x()).max(0.0).sqrt())
+}
+
+fn clearcoat_alpha(gloss: f32) -> f32 {
+    0.1 + (0.001 - 0.1) * gloss
+}
+
+struct Bsdf<'a> {
+    mat: &'a Material,
+    alpha: f32,
+    eta: f32,
+    eta_specular: f32,
+    p_diffuse: f32,
+    p_specular: f32,
+    p_glass: f32,
+    p_clearcoat: f32,
+}
+
+impl<'a> Bsdf<'a> {
+    fn new(mat: &'a Material, wo: Vec3, front_face: bool) -> Self {
+        let eta = if front_face { mat.ior } else { 1.0 / mat.ior };
+        let f0 = (0.08 * mat.specular).clamp(0.0, 0.99).sqrt();
+
+        let (m, t) = (mat.metallic, mat.transmission);
+        let metal = mat.albedo + (Vec3::one() - mat.albedo) * schlick_weight(wo.z());
+        let glass = fresnel_dielectric(wo.z(), eta);
+        let mut b = Bsdf {
+            mat,
+            alpha: (mat.roughness * mat.roughness).max(MIN_ALPHA),
+            eta,
+            eta_specular: (1.0 + f0) / (1.0 - f0),
+            p_diffuse: (1.0 - m) * (1.0 - t) * (luminance(mat.albedo) + mat.sheen),
+            p_specular: 0.0,
+            p_glass: (1.0 - m) * t * (glass + (1.0 - glass) * luminance(mat.albedo)),
+            p_clearcoat: 0.25 * mat.clearcoat * (0.04 + 0.96 * schlick_weight(wo.z())),
+        };
+        b.p_specular = m * luminance(metal)
+            + (1.0 - m) * (1.0 - t) * fresnel_dielectric(wo.z(), b.eta_specular);
+
+        let total = b.p_diffuse + b.p_specular + b.p_glass + b.p_clearcoat;
+        if total > 0.0 {
+            b.p_diffuse /= total;
+            b.p_specular /= total;
+            b.p_glass /= total;
+            b.p_clearcoat /= total;
+        }
+        b
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        if wi.z() == 0.0 {
+            return (Vec3::zero(), 0.0);
+        }
+
+        let mat = self.mat;
+        let (m, t) = (mat.metallic, mat.transmission);
+        let mut f = Vec3::zero();
+        let mut pdf = 0.0;
+
+        if wi.z() > 0.0 {
+            let h = (wo + wi).normalize();
+            let cos_d = wi.dot(h);
+
+            let fl = schlick_weight(wi.z());
+            let fv = schlick_weight(wo.z());
+            let fd90 = 0.5 + 2.0 * mat.roughness * cos_d * cos_d;
+            let retro = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+            let lum = luminance(mat.albedo);
+            let tint = if lum > 0.0 {
+                mat.albedo / lum
+            } else {
+                Vec3::one()
+            };
+            let sheen = mat.sheen * Vec3::one().lerp(tint, mat.sheen_tint) * schlick_weight(cos_d);
+            f += (1.0 - m) * (1.0 - t) * (mat.albedo / PI * retro + sheen);
+            pdf += self.p_diffuse * wi.z() / PI;
+
+            let d = ggx_d(h, self.alpha);
+            let g = smith_g2(wo, wi, self.alpha);
+            let glass = fresnel_dielectric(cos_d, self.eta);
+            let fresnel = m * (mat.albedo + (Vec3::one() - mat.albedo) * schlick_weight(cos_d))
+                + Vec3::splat(
+                    (1.0 - m) * (1.0 - t) * fresnel_dielectric(cos_d, self.eta_specular)
+                        + (1.0 - m) * t * glass,
+                );
+            f += fresnel * d * g / (4.0 * wi.z() * wo.z());
+            let reflect_pdf = ggx_vndf_pdf(wo, h, self.alpha) / (4.0 * cos_d);
+            pdf += (self.p_specular + self.p_glass * glass) * reflect_pdf;
+
+            let alpha_c = clearcoat_alpha(mat.clearcoat_gloss);
+            let d_c = gtr1_d(h, alpha_c);
+            let f_c = 0.04 + 0.96 * schlick_weight(cos_d);
+            let g_c = smith_g2(wo, wi, 0.25);
+            f += Vec3::splat(0.25 * mat.clearcoat * d_c * f_c * g_c / (4.0 * wi.z() * wo.z()));
+            pdf += self.p_clearcoat * d_c * h.z() / (4.0 * cos_d);
+        } else if self.eta != 1.0 {
+            let mut h = (wo + self.eta * wi).normalize();
+            if h.z() < 0.0 {
+                h = -h;
+            }
+            let cos_o = wo.dot(h);
+            let cos_i = wi.dot(h);
+            if cos_o <= 0.0 || cos_i >= 0.0 {
+                return (Vec3::zero(), 0.0);
+            }
+
+            let denom = cos_o + self.eta * cos_i;
+            let glass = fresnel_dielectric(cos_o, self.eta);
+            let d = ggx_d(h, self.alpha);
+            let g = smith_g2(wo, wi, self.alpha);
+            let jacobian = self.eta * self.eta * cos_i.abs() / (denom * denom);
+            f += (1.0 - m) * t * mat.albedo * (1.0 - glass) * d * g * cos_o * jacobian
+                / (wi.z().abs() * wo.z());
+            pdf += self.p_glass * (1.0 - glass) * ggx_vndf_pdf(wo, h, self.alpha) * jacobian;
+        }
+        (f, pdf)
+    }
+
+    fn sample(&self, wo: Vec3, s: Vec3) -> Option<Vec3> {
+        let uv = Vec2::new(s.y(), s.z());
+        let mut x = s.x();
+        if x < self.p_diffuse {
+            return Some(sample_cosine_hemisphere(uv));
+        }
+        x -= self.p_diffuse;
+        if x < self.p_specular {
+            let wi = reflect(-wo, sample_ggx_vndf(wo, self.alpha, uv));
+            return Some(wi).filter(|wi| wi.z() > 0.0);
+        }
+        x -= self.p_specular;
+        if x < self.p_glass {
+            let h = sample_ggx_vndf(wo, self.alpha, uv);
+            if x / self.p_glass < fresnel_dielectric(wo.dot(h), self.eta) {
+                return Some(reflect(-wo, h)).filter(|wi| wi.z() > 0.0);
+            }
+            let wi = if self.eta == 1.0 {
+                -wo
+            } else {
+                refract(-wo, h, 1.0 / self.eta)
+            };
+            return Some(wi).filter(|wi| wi.z() < 0.0);
+        }
+        x -= self.p_glass;
+        if x < self.p_clearcoat {
+            let h = sample_gtr1(clearcoat_alpha(self.mat.clearcoat_gloss), uv);
+            return Some(reflect(-wo, h)).filter(|wi| wi.z() > 0.0);
+        }
+        None
+    }
+}
+
+struct Frame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    fn new(n: Vec3) -> Self {
+        let (t, b) = onb(n);
+        Frame { t, b, n }
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.t), v.dot(self.b), v.dot(self.n))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.t + v.y() * self.b + v.z() * self.n
+    }
+
+    fn local_wo(&self, wo: Vec3) -> Vec3 {
+        let v = self.to_local(wo);
+        Vec3::new(v.x(), v.y(), v.z().max(1e-4)).normalize()
+    }
+}
+
+/// Returns the BSDF value, the sampled direction and its density.
+fn bsdf_sample(mat: &Material, rec: &HitRec, wo: Vec3, s: Vec3) -> Option<(Vec3, Vec3, f32)> {
+    let frame = Frame::new(rec.normal);
+    let v = frame.local_wo(wo);
+    let b = Bsdf::new(mat, v, rec.front_face);
+
+    let l = b.sample(v, s)?.normalize();
+    let wi = frame.to_world(l).normalize();
+
+    if b.eta == 1.0 && l.z() < 0.0 {
+        let f = (1.0 - mat.metallic) * mat.transmission * mat.albedo / l.z().abs();
+        return Some((f, wi, b.p_glass));
+    }
+    let (f, pdf) = b.eval(v, l);
+    Some((f, wi, pdf))
+}
+
+/// BSDF value and density of sampling `wi`, see `bsdf_eval` in bsdf.glsl.
+fn bsdf_eval(mat: &Material, rec: &HitRec, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+    let frame = Frame::new(rec.normal);
+    let v = frame.local_wo(wo);
+    Bsdf::new(mat, v, rec.front_face).eval(v, frame.to_local(wi))
+}
+
+// ---- medium.glsl ----
+
+impl Tracer {
+    fn global_fog(&self) -> Medium {
+        Medium {
+            absorption: self.globals.fog_absorption,
+            scattering: self.globals.fog_scattering,
+            anisotropy: self.globals.fog_anisotropy,
+        }
+    }
+
+    /// Volume box containing `p`, see `volume_at` in medium.glsl.
+    fn volume_at(&self, p: Vec3) -> Option<u32> {
+        self.volumes
+            .iter()
+            .position(|v| p.cmpgt(v.min).all() && p.cmplt(v.max).all())
+            .map(|i| i as u32)
+    }
+
+    /// Medium a ray inside `volume` went through before reaching `rec`, and
+    /// whether it is the volume's density grid, see `segment_medium` in
+    /// medium.glsl.
+    fn segment_medium(&self, rec: &HitRec, volume: Option<u32>) -> (Medium, bool) {
+        let medium = |m: Material| Medium {
+            absorption: m.absorption,
+            scattering: m.scattering,
+            anisotropy: m.anisotropy,
+        };
+        if !rec.front_face && rec.volume.is_none() {
+            let m = self.material(rec.mat_ptr);
+            if m.transmission > 0.0 {
+                return (medium(m), false);
+            }
+        }
+        match volume {
+            Some(v) => {
+                let m = self.material(self.volumes[v as usize].mat_ptr);
+                (medium(m), true)
+            }
+            None => (self.global_fog(), false),
+        }
+    }
+}
+
+impl Tracer {
+    /// Delta tracking through the density grid of a volume, see
+    /// `sample_volume` in medium.glsl.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_volume(
+        &self,
+        index: u32,
+        m: &Medium,
+        r: &Ray,
+        t_max: f32,
+        mut s: Vec2,
+        rng: &mut Sampler,
+        throughput: &mut Vec3,
+    ) -> Option<f32> {
+        let v = &self.volumes[index as usize];
+        let sigma_t = m.absorption + m.scattering;
+        let majorant = self.grid_majorant(v.density) * sigma_t.max_element();
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        let mut t = 0.0;
+        for i in 0..MAX_TRACKING_STEPS {
+            if i > 0 {
+                s = rng.next_tracking_2d();
+            }
+            t -= (1.0 - s.x()).ln() / majorant;
+            if t >= t_max {
+                return None;
+            }
+
+            let density = self.sample_grid(v.density, (r.at(t) - v.min) / (v.max - v.min));
+            let sigma_s = density * m.scattering;
+            let sigma_n = Vec3::splat(majorant) - density * sigma_t;
+            let mut p_s = sigma_s.dot(Vec3::splat(1.0 / 3.0));
+            let p_n = sigma_n.dot(Vec3::splat(1.0 / 3.0));
+            if p_s + p_n <= 0.0 {
+                break;
+            }
+
+            p_s /= p_s + p_n;
+            if s.y() < p_s {
+                *throughput *= sigma_s / (majorant * p_s);
+                return Some(t);
+            }
+            *throughput *= sigma_n / (majorant * (1.0 - p_s));
+        }
+
+        *throughput = Vec3::zero();
+        None
+    }
+
+    /// Ratio tracking through the density grid of a volume, see
+    /// `volume_transmittance` in medium.glsl.
+    fn volume_transmittance(
+        &self,
+        index: u32,
+        m: &Medium,
+        r: &Ray,
+        t_max: f32,
+        rng: &mut Sampler,
+    ) -> Vec3 {
+        let v = &self.volumes[index as usize];
+        let sigma_t = m.absorption + m.scattering;
+        let majorant = self.grid_majorant(v.density) * sigma_t.max_element();
+        let mut tr = Vec3::one();
+        if majorant <= 0.0 {
+            return tr;
+        }
+
+        let mut t = 0.0;
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rng.next_tracking_1d()).ln() / majorant;
+            if t >= t_max {
+                return tr;
+            }
+            let density = self.sample_grid(v.density, (r.at(t) - v.min) / (v.max - v.min));
+            tr *= Vec3::one() - density * sigma_t / majorant;
+        }
+        Vec3::zero()
+    }
+}
+
+/// Returns the distance to the scattering event if the ray scatters before
+/// `t_max`, and weights `throughput` by transmittance over density.
+fn sample_medium(m: &Medium, t_max: f32, s: Vec2, throughput: &mut Vec3) -> Option<f32> {
+    if m.scattering == Vec3::zero() {
+        *throughput *= exp(-m.absorption * t_max);
+        return None;
+    }
+
+    let c = ((s.x() * 3.0) as usize).min(2);
+    let mut t = t_max;
+    if m.scattering[c] > 0.0 {
+        t = (-(1.0 - s.y()).ln() / m.scattering[c]).min(t_max);
+    }
+
+    let tr = exp(-(m.absorption + m.scattering) * t);
+    let density = exp(-m.scattering * t);
+    if t < t_max {
+        *throughput *= m.scattering * tr / (m.scattering * density).dot(Vec3::splat(1.0 / 3.0));
+        return Some(t);
+    }
+    if t_max < f32::MAX {
+        *throughput *= tr / density.dot(Vec3::splat(1.0 / 3.0));
+    }
+    None
+}
+
+fn sample_hg(direction: Vec3, g: f32, s: Vec2) -> Vec3 {
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * s.x()
+    } else {
+        let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * s.x());
+        (1.0 + g * g - sq * sq) / (2.0 * g)
+    }
+    .clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * s.y();
+
+    let (t, b) = onb(direction);
+    (sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * direction).normalize()
+}
+
+// ---- environment.glsl ----
+
+impl Tracer {
+    fn environment_float(&self, i: usize) -> f32 {
+        f32::from_bits(self.environment[i])
+    }
+
+    fn has_environment(&self) -> bool {
+        self.environment[0] > 0
+    }
+
+    fn environment_texel(&self, x: u32, y: u32) -> Vec3 {
+        let i = ENVIRONMENT_HEADER + 3 * (y * self.environment[0] + x) as usize;
+        vec3(&self.environment[i..i + 3])
+    }
+
+    /// Image coordinates of direction `d`, see `environment_uv` in
+    /// environment.glsl.
+    fn environment_uv(&self, d: Vec3) -> Vec2 {
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI) - self.environment_float(3);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+        Vec2::new(u - u.floor(), v)
+    }
+
+    fn environment_direction(&self, uv: Vec2) -> Vec3 {
+        let phi = 2.0 * PI * (uv.x() + self.environment_float(3) - 0.5);
+        let theta = PI * uv.y();
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    /// Bilinearly filtered radiance arriving from direction `d`.
+    fn environment_radiance(&self, d: Vec3) -> Vec3 {
+        if !self.has_environment() {
+            return Vec3::zero();
+        }
+
+        let (width, height) = (self.environment[0], self.environment[1]);
+        let p = self.environment_uv(d) * Vec2::new(width as f32, height as f32) - Vec2::splat(0.5);
+        let f = p - p.floor();
+        let (px, py) = (p.x().floor() as i32, p.y().floor() as i32);
+        let x0 = (px + width as i32) as u32 % width;
+        let x1 = (x0 + 1) % width;
+        let y0 = py.max(0).min(height as i32 - 1) as u32;
+        let y1 = (py + 1).max(0).min(height as i32 - 1) as u32;
+
+        let mix = |a: Vec3, b: Vec3, t: f32| a + (b - a) * t;
+        let c = mix(
+            mix(
+                self.environment_texel(x0, y0),
+                self.environment_texel(x1, y0),
+                f.x(),
+            ),
+            mix(
+                self.environment_texel(x0, y1),
+                self.environment_texel(x1, y1),
+                f.x(),
+            ),
+            f.y(),
+        );
+        self.environment_float(2) * c
+    }
+
+    /// Density over solid angle of picking texel `x`, `y` and a point at
+    /// latitude `v` in it.
+    fn environment_texel_pdf(&self, x: u32, y: u32, v: f32) -> f32 {
+        let (width, height) = (self.environment[0], self.environment[1]);
+        let total =
+            self.environment_float(ENVIRONMENT_HEADER + (3 * width * height + height) as usize - 1);
+        let sin_theta = (PI * v).sin();
+        if total <= 0.0 || sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let row_sin = (PI * (y as f32 + 0.5) / height as f32).sin();
+        let weight = luminance(self.environment_texel(x, y)).max(0.0) * row_sin;
+        weight * (width * height) as f32 / (total * 2.0 * PI * PI * sin_theta)
+    }
+
+    /// Density of `sample_environment` over solid angle.
+    fn environment_pdf(&self, d: Vec3) -> f32 {
+        if !self.has_environment() {
+            return 0.0;
+        }
+
+        let (width, height) = (self.environment[0], self.environment[1]);
+        let uv = self.environment_uv(d);
+        let x = ((uv.x() * width as f32) as u32).min(width - 1);
+        let y = ((uv.y() * height as f32) as u32).min(height - 1);
+        self.environment_texel_pdf(x, y, uv.y())
+    }
+
+    /// Index of one of the `n` cumulative weights from `offset` picked with
+    /// `s`, and where `s` fell inside of it.
+    fn sample_cumulative(&self, offset: usize, n: usize, s: f32) -> (usize, f32) {
+        let t = s * self.environment_float(offset + n - 1);
+        let (mut lo, mut hi) = (0, n - 1);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.environment_float(offset + mid) > t {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        let below = if lo > 0 {
+            self.environment_float(offset + lo - 1)
+        } else {
+            0.0
+        };
+        let size = self.environment_float(offset + lo) - below;
+        let f = if size > 0.0 {
+            ((t - below) / size).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+        (lo, f)
+    }
+
+    /// Direction towards a texel picked by luminance and its density,
+    /// `None` without an environment or for a black one.
+    fn sample_environment(&self, s: Vec2) -> Option<(Vec3, f32)> {
+        if !self.has_environment() {
+            return None;
+        }
+
+        let (width, height) = (self.environment[0] as usize, self.environment[1] as usize);
+        let marginal = ENVIRONMENT_HEADER + 3 * width * height;
+        if self.environment_float(marginal + height - 1) <= 0.0 {
+            return None;
+        }
+
+        let (y, fy) = self.sample_cumulative(marginal, height, s.x());
+        let (x, fx) = self.sample_cumulative(marginal + height + y * width, width, s.y());
+        let uv = Vec2::new(
+            (x as f32 + fx) / width as f32,
+            (y as f32 + fy) / height as f32,
+        );
+        let pdf = self.environment_texel_pdf(x as u32, y as u32, uv.y());
+        Some((self.environment_direction(uv), pdf))
+    }
+
+    fn has_sun(&self) -> bool {
+        self.has_environment() && self.environment_float(7) > 0.0
+    }
+
+    fn sun_direction(&self) -> Vec3 {
+        vec3(&self.environment[4..7])
+    }
+
+    fn in_sun(&self, d: Vec3) -> bool {
+        self.has_sun() && 1.0 - d.dot(self.sun_direction()) <= self.environment_float(7)
+    }
+
+    /// Radiance of the sun seen in direction `d`, zero outside of its disk.
+    fn sun_radiance(&self, d: Vec3) -> Vec3 {
+        if !self.in_sun(d) {
+            return Vec3::zero();
+        }
+        self.environment_float(2) * vec3(&self.environment[8..11])
+    }
+
+    /// Density of `sample_sun` over solid angle.
+    fn sun_pdf(&self, d: Vec3) -> f32 {
+        if !self.in_sun(d) {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * self.environment_float(7))
+    }
+
+    /// Direction towards the disk of the sun and its density.
+    fn sample_sun(&self, s: Vec2) -> Option<(Vec3, f32)> {
+        if !self.has_sun() {
+            return None;
+        }
+
+        let one_minus_cos_max = self.environment_float(7);
+        let pdf = 1.0 / (2.0 * PI * one_minus_cos_max);
+        let w = self.sun_direction();
+        let (t, b) = onb(w);
+        let c = sample_cone(one_minus_cos_max, s);
+        Some(((c.x() * t + c.y() * b + c.z() * w).normalize(), pdf))
+    }
+}
+
+// ---- light.glsl ----
+
+const MAX_SHADOW_STEPS: u32 = 8;
+
+/// Power heuristic with an exponent of two, see `mis_weight` in light.glsl.
+fn mis_weight(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    a / (a + other * other)
+}
+
+/// 1 - cos of the cap of `l` visible from `p`, zero from inside the light
+/// and from points on it.
+fn sphere_light_cap(l: &Light, p: Vec3) -> f32 {
+    let w = l.center - p;
+    let sin2_max = l.radius * l.radius / w.dot(w);
+    if sin2_max >= 0.998 {
+        return 0.0;
+    }
+    sin2_max / (1.0 + (1.0 - sin2_max).sqrt())
+}
+
+/// Density of `sample_sphere_light` over solid angle.
+fn sphere_light_pdf(l: &Light, p: Vec3) -> f32 {
+    let cap = sphere_light_cap(l, p);
+    if cap > 0.0 {
+        1.0 / (2.0 * PI * cap)
+    } else {
+        0.0
+    }
+}
+
+/// Direction towards the visible cap of `l` and its density in solid angle,
+/// `None` from inside the light.
+fn sample_sphere_light(l: &Light, p: Vec3, s: Vec2) -> Option<(Vec3, f32)> {
+    let one_minus_cos_max = sphere_light_cap(l, p);
+    if one_minus_cos_max <= 0.0 {
+        return None;
+    }
+
+    let pdf = 1.0 / (2.0 * PI * one_minus_cos_max);
+
+    let w = (l.center - p).normalize();
+    let (t, b) = onb(w);
+    let c = sample_cone(one_minus_cos_max, s);
+    let wi = c.x() * t + c.y() * b + c.z() * w;
+    Some((wi.normalize(), pdf))
+}
+
+impl Tracer {
+    /// Emissive spheres, then the environment and its sun if there are any.
+    fn light_count(&self) -> usize {
+        self.lights.len() + self.has_environment() as usize + self.has_sun() as usize
+    }
+
+    /// Radiance of the environment or the sun in direction `d` if light
+    /// `index` is one of them.
+    fn distant_radiance(&self, index: u32, d: Vec3) -> Vec3 {
+        match index as usize {
+            i if i == self.lights.len() => self.environment_radiance(d),
+            i if i == self.lights.len() + 1 => self.sun_radiance(d),
+            _ => Vec3::zero(),
+        }
+    }
+
+    /// Emission of light `index` seen along `r` through the media on the
+    /// way, see `light_radiance` in light.glsl.
+    fn light_radiance(
+        &self,
+        mut r: Ray,
+        index: u32,
+        mut volume: Option<u32>,
+        rng: &mut Sampler,
+    ) -> Vec3 {
+        let mut tr = Vec3::one();
+        for _ in 0..MAX_SHADOW_STEPS {
+            let rec = match self.hit_world(&r, 0.001, f32::MAX) {
+                Some(rec) => rec,
+                None => {
+                    let fog = self.global_fog();
+                    return tr
+                        * exp(-(fog.absorption + fog.scattering) * f32::MAX)
+                        * self.distant_radiance(index, r.direction);
+                }
+            };
+
+            let (medium, in_volume) = self.segment_medium(&rec, volume);
+            match volume {
+                Some(v) if in_volume => {
+                    tr *= self.volume_transmittance(v, &medium, &r, rec.t, rng);
+                }
+                _ => tr *= exp(-(medium.absorption + medium.scattering) * rec.t),
+            }
+
+            if rec.volume.is_none() {
+                if rec.light != Some(index) {
+                    return Vec3::zero();
+                }
+                let m = self.material(rec.mat_ptr);
+                return tr
+                    * m.emission
+                    * self.sample_texture(m.emission_texture, rec.uv, rec.point);
+            }
+            volume = rec.volume.filter(|_| rec.front_face);
+            r.origin = rec.point;
+        }
+        Vec3::zero()
+    }
+
+    /// Weight of the emission of `light` reached by a BSDF sample, see
+    /// `light_hit_weight` in light.glsl.
+    fn light_hit_weight(&self, light: u32, origin: Vec3, bsdf_pdf: f32) -> f32 {
+        if bsdf_pdf <= 0.0 {
+            return 1.0;
+        }
+        let light_pdf =
+            sphere_light_pdf(&self.lights[light as usize], origin) / self.light_count() as f32;
+        mis_weight(bsdf_pdf, light_pdf)
+    }
+
+    /// Light of the environment and the sun reached by a BSDF sample leaving
+    /// the scene, see `escaped_radiance` in light.glsl.
+    fn escaped_radiance(&self, d: Vec3, bsdf_pdf: f32) -> Vec3 {
+        // Without an environment there may be no light to divide by
+        if !self.has_environment() {
+            return Vec3::zero();
+        }
+
+        let mut env = self.environment_radiance(d);
+        let mut sun = self.sun_radiance(d);
+        if bsdf_pdf > 0.0 {
+            let count = self.light_count() as f32;
+            env *= mis_weight(bsdf_pdf, self.environment_pdf(d) / count);
+            sun *= mis_weight(bsdf_pdf, self.sun_pdf(d) / count);
+        }
+        env + sun
+    }
+
+    /// Direct light from one light picked uniformly and weighted against BSDF
+    /// sampling, see `sample_direct` in light.glsl.
+    fn sample_direct(
+        &self,
+        mat: &Material,
+        rec: &HitRec,
+        wo: Vec3,
+        volume: Option<u32>,
+        rng: &mut Sampler,
+    ) -> Vec3 {
+        let count = self.light_count();
+        if count == 0 {
+            return Vec3::zero();
+        }
+
+        let s = rng.next_3d();
+        let index = ((s.x() * count as f32) as usize).min(count - 1);
+        let s = Vec2::new(s.y(), s.z());
+        let sample = match self.lights.get(index) {
+            Some(l) => sample_sphere_light(l, rec.point, s),
+            None if index == self.lights.len() => self.sample_environment(s),
+            None => self.sample_sun(s),
+        };
+        let (wi, pdf) = match sample {
+            Some(sample) if sample.1 > 0.0 => sample,
+            _ => return Vec3::zero(),
+        };
+
+        let (f, bsdf_pdf) = bsdf_eval(mat, rec, wo, wi);
+        if f == Vec3::zero() {
+            return Vec3::zero();
+        }
+        let r = Ray {
+            origin: rec.point,
+            direction: wi,
+        };
+        let le = self.light_radiance(r, index as u32, volume, rng);
+        let pdf = pdf / count as f32;
+        f * wi.dot(rec.normal).abs() * le * mis_weight(pdf, bsdf_pdf) / pdf
+    }
+}
+
+// ---- common.glsl ----
+
+fn hash3(mut x: [u32; 3]) -> Vec3 {
+    const K: u32 = 1103515245;
+    for _ in 0..3 {
+        x = [
+            ((x[0] >> 8) ^ x[1]).wrapping_mul(K),
+            ((x[1] >> 8) ^ x[2]).wrapping_mul(K),
+            ((x[2] >> 8) ^ x[0]).wrapping_mul(K),
+        ];
+    }
+    Vec3::new(x[0] as f32, x[1] as f32, x[2] as f32) * (1.0 / u32::MAX as f32)
+}
+
+/// Uniform over the solid angle of a cone around +z.
+fn sample_cone(one_minus_cos_max: f32, s: Vec2) -> Vec3 {
+    let one_minus_cos = s.x() * one_minus_cos_max;
+    let cos_theta = 1.0 - one_minus_cos;
+    let sin_theta = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0).sqrt();
+    let phi = 2.0 * PI * s.y();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn random_in_unit_disk(s: Vec2) -> Vec2 {
+    let r = s.x().sqrt();
+    let phi = 2.0 * std::f32::consts::PI * s.y();
+    Vec2::new(r * phi.cos(), r * phi.sin())
+}
+
+// GLSL builtins
+fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let n_dot_i = n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - n_dot_i * n_dot_i);
+    if k < 0.0 {
+        Vec3::zero()
+    } else {
+        eta * i - (eta * n_dot_i + k.sqrt()) * n
+    }
+}
+
+fn exp(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().exp(), v.y().exp(), v.z().exp())
+}
+
+// ---- sampler.glsl ----
+
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Stream of numbers of sample `index` in a pixel, see sampler.glsl.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    kind: u32,
+    seed: u32,
+    index: u32,
+    dimension: u32,
+    tracking: u32,
+}
+
+const TRACKING_DIMENSION: u32 = 0x80000000;
+
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn uint_to_float(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / 16777216.0)
+}
+
+fn scrambled_radical_inverse(base: u32, mut index: u32, mut seed: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut f = inv_base;
+    let mut r = 0.0;
+    while 1.0 - (base - 1) as f32 * f < 1.0 {
+        let digit = index % base;
+        index /= base;
+        let a = 1 + seed % (base - 1);
+        let c = (seed >> 16) % base;
+        r += f * ((a * digit + c) % base) as f32;
+        f *= inv_base;
+        seed = pcg(seed ^ digit);
+    }
+    r.min(1.0 - f32::EPSILON / 2.0)
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    for &k in [0x6c50b47c, 0xb82f1e52, 0xc7afe638, 0x8d22f6e6u32].iter() {
+        x ^= x.wrapping_mul(k);
+    }
+    x.reverse_bits()
+}
+
+fn sobol_2d(mut index: u32) -> [u32; 2] {
+    let mut r = [0, 0];
+    let mut v = 1 << 31;
+    let mut i = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            r[0] ^= 1 << (31 - i);
+            r[1] ^= v;
+        }
+        v ^= v >> 1;
+        index >>= 1;
+        i += 1;
+    }
+    r
+}
+
+impl Sampler {
+    /// `kind` is a `scene::Sampler` as in `Globals`.
+    pub fn new(kind: u32, pixel: [u32; 2], index: u32) -> Self {
+        Sampler {
+            kind,
+            seed: pcg(pixel[0] ^ pcg(pixel[1])),
+            index,
+            dimension: 0,
+            tracking: 0,
+        }
+    }
+
+    fn hash_dimension(&self, dimension: u32) -> u32 {
+        pcg(self.seed ^ pcg(dimension))
+    }
+
+    fn independent_1d(&self, dimension: u32) -> f32 {
+        uint_to_float(pcg(self.hash_dimension(dimension) ^ self.index))
+    }
+
+    fn halton_1d(&self, dimension: u32) -> f32 {
+        match HALTON_PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                scrambled_radical_inverse(base, self.index, self.hash_dimension(dimension))
+            }
+            None => self.independent_1d(dimension),
+        }
+    }
+
+    fn owen_sobol_2d(&self, dimension: u32) -> Vec2 {
+        let seed = self.hash_dimension(dimension);
+        let [x, y] = sobol_2d(nested_uniform_scramble(self.index, seed));
+        let x = nested_uniform_scramble(x, pcg(seed));
+        let y = nested_uniform_scramble(y, pcg(seed.wrapping_add(1)));
+        Vec2::new(uint_to_float(x), uint_to_float(y))
+    }
+
+    pub fn next_1d(&mut self) -> f32 {
+        let d = self.dimension;
+        self.dimension += 1;
+        match self.kind {
+            SAMPLER_HALTON => self.halton_1d(d),
+            SAMPLER_SOBOL => self.owen_sobol_2d(d).x(),
+            _ => self.independent_1d(d),
+        }
+    }
+
+    pub fn next_2d(&mut self) -> Vec2 {
+        let d = self.dimension;
+        self.dimension += 2;
+        match self.kind {
+            SAMPLER_HALTON => Vec2::new(self.halton_1d(d), self.halton_1d(d + 1)),
+            SAMPLER_SOBOL => self.owen_sobol_2d(d),
+            _ => Vec2::new(self.independent_1d(d), self.independent_1d(d + 1)),
+        }
+    }
+
+    pub fn next_3d(&mut self) -> Vec3 {
+        let x = self.next_1d();
+        let s = self.next_2d();
+        Vec3::new(x, s.x(), s.y())
+    }
+
+    /// Independent numbers for loops of varying length, without taking
+    /// dimensions of the sample.
+    fn next_tracking_1d(&mut self) -> f32 {
+        let d = TRACKING_DIMENSION + self.tracking;
+        self.tracking += 1;
+        self.independent_1d(d)
+    }
+
+    fn next_tracking_2d(&mut self) -> Vec2 {
+        let x = self.next_tracking_1d();
+        Vec2::new(x, self.next_tracking_1d())
+    }
+}
+
+// ---- intersection.glsl ----
+
+fn set_face_normal(rec: &mut HitRec, r: &Ray, outward_normal: Vec3) {
+    rec.front_face = r.direction.dot(outward_normal) < 0.0;
+    rec.normal = if rec.front_face {
+        outward_normal
+    } else {
+        -outward_normal
+    };
+}
+
+fn hit_sphere(s: &Sphere, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRec> {
+    let oc = r.origin - s.center;
+    let half_b = oc.dot(r.direction);
+    let c = oc.dot(oc) - s.radius * s.radius;
+    let discriminant = half_b * half_b - c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let mut temp = -half_b - root;
+    if temp > t_max || temp < t_min {
+        temp = -half_b + root;
+        if temp > t_max || temp < t_min {
+            return None;
+        }
+    }
+
+    let mut rec = HitRec {
+        t: temp,
+        point: r.at(temp),
+        mat_ptr: s.mat_ptr,
+        light: Some(s.light).filter(|l| *l != NO_LIGHT),
+        ..Default::default()
+    };
+    let outward_normal = (rec.point - s.center) / s.radius;
+    set_face_normal(&mut rec, r, outward_normal);
+    // Longitude around y and latitude from the north pole
+    let n = outward_normal;
+    rec.uv = Vec2::new(
+        (-n.z()).atan2(n.x()) / (2.0 * PI) + 0.5,
+        n.y().clamp(-1.0, 1.0).acos() / PI,
+    );
+    let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt().max(1e-4);
+    rec.dpdu = 2.0 * PI * s.radius * Vec3::new(n.z(), 0.0, -n.x());
+    rec.dpdv = PI
+        * s.radius
+        * Vec3::new(
+            n.x() * n.y() / sin_theta,
+            -sin_theta,
+            n.z() * n.y() / sin_theta,
+        );
+    Some(rec)
+}
+
+// Watertight ray/triangle intersection
+// http://jcgt.org/published/0002/01/05/paper.pdf
+fn hit_triangle(tri: &Triangle, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRec> {
+    let dir: [f32; 3] = r.direction.into();
+
+    // Permute axes so the ray direction's largest component is z
+    let ad = r.direction.abs();
+    let kz = if ad.x() > ad.y() {
+        if ad.x() > ad.z() {
+            0
+        } else {
+            2
+        }
+    } else if ad.y() > ad.z() {
+        1
+    } else {
+        2
+    };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear so the ray points along +z
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1.0 / dir[kz];
+
+    let a: [f32; 3] = (tri.v[0] - r.origin).into();
+    let b: [f32; 3] = (tri.v[1] - r.origin).into();
+    let c: [f32; 3] = (tri.v[2] - r.origin).into();
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentrics, edges shared by two triangles agree on the sign
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    if t > t_max || t < t_min {
+        return None;
+    }
+
+    let (u, v, w) = (u / det, v / det, w / det);
+    let geometric_normal = (tri.v[1] - tri.v[0]).cross(tri.v[2] - tri.v[0]).normalize();
+    let mut shading_normal = (u * tri.n[0] + v * tri.n[1] + w * tri.n[2]).normalize();
+
+    let front_face = r.direction.dot(geometric_normal) < 0.0;
+    if shading_normal.dot(geometric_normal) < 0.0 {
+        shading_normal = -shading_normal;
+    }
+
+    // Tangents follow the texture coordinates, any frame without them
+    let e1 = tri.v[1] - tri.v[0];
+    let e2 = tri.v[2] - tri.v[0];
+    let duv1 = tri.uv[1] - tri.uv[0];
+    let duv2 = tri.uv[2] - tri.uv[0];
+    let uv_det = duv1.x() * duv2.y() - duv1.y() * duv2.x();
+    let (dpdu, dpdv) = if uv_det.abs() > 1e-12 {
+        (
+            (duv2.y() * e1 - duv1.y() * e2) / uv_det,
+            (duv1.x() * e2 - duv2.x() * e1) / uv_det,
+        )
+    } else {
+        onb(geometric_normal)
+    };
+    Some(HitRec {
+        t,
+        point: r.at(t),
+        front_face,
+        normal: if front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        },
+        mat_ptr: tri.mat_ptr,
+        uv: u * tri.uv[0] + v * tri.uv[1] + w * tri.uv[2],
+        volume: None,
+        light: None,
+        dpdu,
+        dpdv,
+    })
+}
+
+fn hit_box(min: Vec3, max: Vec3, r: &Ray, inv_dir: Vec3) -> bool {
+    let tbot = inv_dir * (min - r.origin);
+    let ttop = inv_dir * (max - r.origin);
+    let tmin = ttop.min(tbot);
+    let tmax = ttop.max(tbot);
+    let t0 = tmin.x().max(tmin.y()).max(tmin.z());
+    let t1 = tmax.x().min(tmax.y()).min(tmax.z());
+
+    t1 > t0.max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::{BuildStrategy, BVH};
+    use crate::environment::Environment;
+    use crate::geometry;
+    use crate::scene::Sampler::{Independent, Sobol};
+    use crate::texture::Texture;
+    use std::path::Path;
+
+    fn rng(kind: crate::scene::Sampler, index: u32) -> Sampler {
+        Sampler::new(kind as u32, [7, 3], index)
+    }
+
+    fn sample_sphere_uniform(s: Vec2) -> Vec3 {
+        let phi = 2.0 * PI * s.x();
+        let cos_theta = 1.0 - 2.0 * s.y();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        Vec3::new(phi.cos() * sin_theta, cos_theta, phi.sin() * sin_theta)
+    }
+
+    fn tracer(scene: &Scene, width: u32, height: u32) -> Tracer {
+        let bvh = BVH::from_primitives(&scene.spheres, &scene.triangles, &scene.volumes, scene.bvh);
+        Tracer::new(
+            scene.globals(width, height),
+            &bvh.as_bytes(),
+            &bvh.triangles().as_bytes(),
+            &scene.materials.as_bytes(),
+            &scene.textures.as_bytes(),
+            &bvh.volumes().as_bytes(),
+            &scene.lights().as_bytes(),
+            &scene.environment.as_bytes(),
+            &Grid::atlas(&scene.textures),
+        )
+    }
+
+    /// Closest hit found by testing every primitive.
+    fn hit_all(scene: &Scene, r: &Ray) -> Option<HitRec> {
+        let mut rec = None;
+        let mut closest = f32::MAX;
+        for s in scene.spheres.iter() {
+            let s = Sphere {
+                center: s.center,
+                radius: s.radius,
+                mat_ptr: s.mat_index,
+                light: s.light_index,
+            };
+            if let Some(hit) = hit_sphere(&s, r, 0.001, closest) {
+                closest = hit.t;
+                rec = Some(hit);
+            }
+        }
+        for t in scene.triangles.iter() {
+            let t = Triangle {
+                v: t.vertices,
+                n: t.normals,
+                uv: t.uvs,
+                mat_ptr: t.mat_index,
+            };
+            if let Some(hit) = hit_triangle(&t, r, 0.001, closest) {
+                closest = hit.t;
+                rec = Some(hit);
+            }
+        }
+        rec
+    }
+
+    #[test]
+    fn test_hit_world() {
+        let strategies = [BuildStrategy::Median, BuildStrategy::default()];
+        for path in ["scenes/default.ron", "scenes/cornell.ron"].iter() {
+            let mut scene = Scene::load(path).unwrap();
+            for strategy in strategies.iter() {
+                scene.bvh = *strategy;
+                let tracer = tracer(&scene, 1, 1);
+
+                // The traversal must find the same closest hit as testing
+                // every primitive
+                let mut rng = rng(Independent, 0);
+                for _ in 0..500 {
+                    let offset = rng.next_2d() - Vec2::splat(0.5);
+                    let r = Ray {
+                        origin: scene.camera.look_from + offset.extend(0.0),
+                        direction: sample_sphere_uniform(rng.next_2d()),
+                    };
+
+                    let expected = hit_all(&scene, &r);
+                    let rec = tracer.hit_world(&r, 0.001, f32::MAX);
+                    assert_eq!(rec.map(|r| r.t), expected.map(|r| r.t));
+                    assert_eq!(rec.map(|r| r.mat_ptr), expected.map(|r| r.mat_ptr));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        // Inside a black light every path ends after one bounce
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, -1.0)),
+                materials: [(name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (color: (1.0, 0.5, 0.25)))],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: 0)],
+            )
+        "#;
+        let mut scene = Scene::parse(src, Path::new(".")).unwrap();
+        for p in tracer(&scene, 8, 4).render(2) {
+            assert_eq!(p, [1.0, 0.5, 0.25]);
+        }
+
+        // A mirror sees itself again at every bounce until max_depth
+        scene.materials[0] = scene.materials[0].with_metallic(1.0).with_roughness(0.0);
+        scene.materials[0].albedo = [0.5; 3];
+        scene.integrator.roulette_depth = scene.integrator.max_depth;
+        let expected = (1.0 - 0.5f32.powi(16)) / (1.0 - 0.5);
+        for p in tracer(&scene, 8, 4).render(2) {
+            for (c, e) in p.iter().zip([1.0, 0.5, 0.25].iter()) {
+                assert!((c - e * expected).abs() < 1e-5, "{:?}", p);
+            }
+        }
+
+        // Russian roulette ends paths early without changing the average
+        scene.integrator.roulette_depth = 1;
+        let pixels = tracer(&scene, 8, 4).render(256);
+        let mean = pixels.iter().map(|p| p[0]).sum::<f32>() / pixels.len() as f32;
+        assert!((mean - expected).abs() < 0.02 * expected, "{}", mean);
+
+        // Nothing in front of the camera
+        scene.spheres = vec![geometry::Sphere::new(Vec3::unit_z(), 0.5, 0)];
+        for p in tracer(&scene, 8, 4).render(2) {
+            assert_eq!(p, [0.0; 3]);
+        }
+    }
+
+    #[test]
+    fn test_bsdf() {
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, -1.0)),
+                materials: [
+                    (name: "plastic", albedo: (1.0, 1.0, 1.0)),
+                    (name: "metal", albedo: (1.0, 0.8, 0.5), metallic: 1.0, roughness: 0.3),
+                    (name: "glass", albedo: (1.0, 1.0, 1.0), transmission: 1.0, roughness: 0.4),
+                    (name: "velvet", albedo: (0.5, 0.1, 0.1), sheen: 1.0, clearcoat: 1.0, clearcoat_gloss: 0.5),
+                    (name: "matte", albedo: (0.8, 0.4, 0.2), specular: 0.0),
+                ],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "matte")],
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new(".")).unwrap();
+        let tracer = tracer(&scene, 1, 1);
+        let matte = scene.spheres[0].mat_index;
+
+        for i in 0..scene.materials.len() as u32 {
+            let mat = tracer.material(i);
+            for cos_o in [1.0f32, 0.6, 0.2].iter() {
+                let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, *cos_o);
+                let b = Bsdf::new(&mat, wo, true);
+
+                // Integrate over the sphere of directions on a grid
+                let n = 400;
+                let (mut pdf_sum, mut albedo) = (0.0, Vec3::zero());
+                for j in 0..n * n {
+                    let theta = PI * ((j / n) as f32 + 0.5) / n as f32;
+                    let phi = 2.0 * PI * ((j % n) as f32 + 0.5) / n as f32;
+                    let wi = Vec3::new(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    let (f, pdf) = b.eval(wo, wi);
+                    let d_omega = theta.sin() * 2.0 * PI * PI / (n * n) as f32;
+                    pdf_sum += pdf * d_omega;
+                    albedo += f * theta.cos().abs() * d_omega;
+                }
+
+                // Some microfacet samples end up below the horizon
+                assert!(
+                    pdf_sum > 0.9 && pdf_sum < 1.01,
+                    "{} {}: {}",
+                    i,
+                    cos_o,
+                    pdf_sum
+                );
+
+                // Importance sampled estimate of the same integral
+                let samples = 100_000;
+                let mut estimate = Vec3::zero();
+                for j in 0..samples {
+                    let s = hash3([j, i, (cos_o * 10.0) as u32]);
+                    if let Some(wi) = b.sample(wo, s) {
+                        let (f, pdf) = b.eval(wo, wi.normalize());
+                        if i == matte {
+                            // Only the diffuse lobe is left, sampled by cosine
+                            assert!((pdf - wi.z() / PI).abs() < 1e-4, "{} {}", pdf, wi);
+                        }
+                        if pdf > 0.0 {
+                            estimate += f * wi.z().abs() / pdf;
+                        }
+                    }
+                }
+                let estimate = estimate / samples as f32;
+
+                for c in 0..3 {
+                    assert!(
+                        (estimate[c] - albedo[c]).abs() < 0.03,
+                        "{} {}: {:?} {:?}",
+                        i,
+                        cos_o,
+                        estimate,
+                        albedo
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_textures() {
+        // A narrow view of a textured light around the camera
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, -1.0), vfov: 1.0),
+                materials: [(name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (intensity: 2.0))],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: 0)],
+            )
+        "#;
+        let mut scene = Scene::parse(src, Path::new(".")).unwrap();
+        scene.materials[0].emission_texture = 0;
+
+        // sRGB decoding
+        scene.textures = vec![Texture::from_rgba8(1, 1, &[188, 188, 188, 255], true)];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert!((p[0] - 2.0 * 0.5029).abs() < 1e-3, "{:?}", p);
+        }
+
+        // Red on the +z half of the sphere, blue on -z
+        scene.textures = vec![Texture::from_rgba8(
+            2,
+            1,
+            &[255, 0, 0, 0, 0, 0, 255, 0],
+            false,
+        )];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert!(p[2] > 1.9 && p[0] < 0.1, "{:?}", p);
+        }
+        scene.camera.look_at = Vec3::unit_z();
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert!(p[0] > 1.9 && p[2] < 0.1, "{:?}", p);
+        }
+        // Halfway between both texel centers
+        scene.camera.look_at = Vec3::unit_x();
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert!(
+                (p[0] - 1.0).abs() < 0.1 && (p[2] - 1.0).abs() < 0.1,
+                "{:?}",
+                p
+            );
+        }
+    }
+
+    #[test]
+    fn test_normal_mapping() {
+        // Constant maps on a unit sphere hit at +x, where u runs along -z and v
+        // along -y
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (1.0, 0.0, 0.0), vfov: 1.0),
+                materials: [
+                    (name: "tilted", albedo: (0.5, 0.5, 0.5), normal_texture: Some(Checker(even: (0.8536, 0.5, 0.8536), odd: (0.8536, 0.5, 0.8536)))),
+                    (name: "up", albedo: (0.5, 0.5, 0.5), normal_texture: Some(Checker(even: (0.5, 0.8536, 0.8536), odd: (0.5, 0.8536, 0.8536)))),
+                    (name: "ramp", albedo: (0.5, 0.5, 0.5), bump_texture: Some(Gradient(from: (0.0, 0.0, 0.0), to: (1.0, 1.0, 1.0), start: (0.0, -1.0, 0.0), end: (0.0, 1.0, 0.0)))),
+                ],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: 0)],
+            )
+        "#;
+        let mut scene = Scene::parse(src, Path::new(".")).unwrap();
+        let r = Ray {
+            origin: Vec3::new(5.0, 0.0, 0.0),
+            direction: -Vec3::unit_x(),
+        };
+        let expected = [
+            Vec3::new(1.0, 0.0, -1.0).normalize(),
+            Vec3::new(1.0, 1.0, 0.0).normalize(),
+            // Heights rise by a half per unit along y
+            Vec3::new(1.0, -0.5, 0.0).normalize(),
+        ];
+        for (i, n) in expected.iter().enumerate() {
+            scene.spheres[0].mat_index = i as u32;
+            let tracer = tracer(&scene, 1, 1);
+            let rec = tracer.hit_world(&r, 0.001, f32::MAX).unwrap();
+            assert!(rec.dpdu.dot(rec.normal).abs() < 1e-5);
+            assert!(rec.dpdv.dot(rec.normal).abs() < 1e-5);
+
+            let material = tracer.material(rec.mat_ptr);
+            let perturbed = tracer.perturb_normal(&material, &rec);
+            assert!((perturbed - *n).length() < 1e-2, "{} {:?}", i, perturbed);
+
+            // Seen from inside, the map mirrors with the surface
+            let inside = Ray {
+                origin: Vec3::zero(),
+                direction: Vec3::unit_x(),
+            };
+            let rec = tracer.hit_world(&inside, 0.001, f32::MAX).unwrap();
+            let perturbed = tracer.perturb_normal(&material, &rec);
+            assert!((perturbed + *n).length() < 1e-2, "{} {:?}", i, perturbed);
+        }
+    }
+
+    #[test]
+    fn test_procedural_textures() {
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (1.0, 0.0, 0.0), vfov: 1.0),
+                materials: [(name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (intensity: 2.0))],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: 0)],
+            )
+        "#;
+        let mut scene = Scene::parse(src, Path::new(".")).unwrap();
+        scene.materials[0].emission_texture = 0;
+
+        // The hit point (10, 0, 0) is in an odd cell at scale 0.1, an even
+        // one at 0.2
+        for (scale, expected) in [(0.1, [0.0, 2.0]), (0.2, [2.0, 0.0])].iter() {
+            scene.textures = vec![Texture::Checker {
+                even: [1.0, 0.0, 0.0],
+                odd: [0.0, 1.0, 0.0],
+                scale: *scale,
+            }];
+            for p in tracer(&scene, 4, 4).render(1) {
+                assert_eq!(&p[0..2], expected);
+            }
+        }
+
+        // Halfway along the gradient, clamped past its end
+        scene.textures = vec![Texture::Gradient {
+            from: [0.0; 3],
+            to: [1.0; 3],
+            start: [-10.0, 0.0, 0.0],
+            end: [30.0, 0.0, 0.0],
+        }];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert!((p[0] - 1.0).abs() < 1e-2, "{:?}", p);
+        }
+        scene.textures = vec![Texture::Gradient {
+            from: [0.0; 3],
+            to: [1.0; 3],
+            start: [-10.0, 0.0, 0.0],
+            end: [0.0, 0.0, 0.0],
+        }];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert_eq!(p[0], 2.0);
+        }
+        // Not NaN when the ends coincide
+        scene.textures = vec![Texture::Gradient {
+            from: [0.0; 3],
+            to: [1.0; 3],
+            start: [1.0; 3],
+            end: [1.0; 3],
+        }];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert_eq!(p[0], 0.0);
+        }
+    }
+
+    #[test]
+    fn test_absorption() {
+        // From the center of a matched glass ball every ray travels one unit
+        // inside it before reaching the light
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, -1.0)),
+                materials: [
+                    (name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (color: (1.0, 1.0, 1.0))),
+                    (name: "tinted", albedo: (1.0, 1.0, 1.0), transmission: 1.0, roughness: 0.0, ior: 1.0, absorption: (0.0, 0.5, 1.0)),
+                ],
+                primitives: [
+                    Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: "light"),
+                    Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "tinted"),
+                ],
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new(".")).unwrap();
+        let expected = [1.0, (-0.5f32).exp(), (-1.0f32).exp()];
+        for p in tracer(&scene, 4, 4).render(1) {
+            for c in 0..3 {
+                assert!((p[c] - expected[c]).abs() < 1e-4, "{:?}", p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_direct_lighting() {
+        // A light straight above a matte floor, whose cap covers sin^2 = 0.01
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 1.0, 0.0), look_at: (0.0, 0.0, 0.0), vfov: 1.0),
+                materials: [
+                    (name: "floor", albedo: (0.5, 0.5, 0.5), specular: 0.0),
+                    (name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (intensity: 2.0)),
+                ],
+                primitives: [
+                    Triangle(vertices: ((-10.0, 0.0, -10.0), (0.0, 0.0, 10.0), (10.0, 0.0, -10.0)), material: "floor"),
+                    Sphere(center: (0.0, 5.0, 0.0), radius: 0.5, material: "light"),
+                ],
+            )
+        "#;
+        let mut scene = Scene::parse(src, Path::new(".")).unwrap();
+        assert_eq!(scene.spheres[0].light_index, 0);
+        assert_eq!(scene.lights().len(), 1);
+
+        // Light reflected straight up, by both strategies or BSDF sampling alone
+        let direct = |scene: &Scene, n: u32, mis: bool| {
+            let tracer = tracer(scene, 1, 1);
+            let r = Ray {
+                origin: Vec3::unit_y(),
+                direction: -Vec3::unit_y(),
+            };
+            let rec = tracer.hit_world(&r, 0.001, f32::MAX).unwrap();
+            let material = tracer.material(rec.mat_ptr);
+            let wo = Vec3::unit_y();
+            let mut sum = Vec3::zero();
+            for i in 0..n {
+                let mut rng = rng(Sobol, i);
+                if mis {
+                    sum += tracer.sample_direct(&material, &rec, wo, None, &mut rng);
+                }
+                let (f, wi, pdf) = match bsdf_sample(&material, &rec, wo, rng.next_3d()) {
+                    Some(sample) if sample.2 > 0.0 => sample,
+                    _ => continue,
+                };
+                let r = Ray {
+                    origin: rec.point,
+                    direction: wi,
+                };
+                if let Some(hit) = tracer.hit_world(&r, 0.001, f32::MAX) {
+                    if let Some(light) = hit.light {
+                        let weight =
+                            tracer.light_hit_weight(light, rec.point, pdf * mis as u32 as f32);
+                        let le = tracer.material(hit.mat_ptr).emission;
+                        sum += f * wi.dot(rec.normal).abs() / pdf * le * weight;
+                    }
+                }
+            }
+            sum / n as f32
+        };
+
+        // Albedo / pi times the irradiance pi * L * sin^2
+        let l = direct(&scene, 1000, true);
+        assert!(
+            (l - Vec3::splat(0.01)).abs().max_element() < 2e-4,
+            "{:?}",
+            l
+        );
+
+        // A glossy metal converges to what BSDF sampling finds by itself
+        scene.materials[0] = scene.materials[0].with_metallic(1.0).with_roughness(0.3);
+        let reference = direct(&scene, 100_000, false);
+        let l = direct(&scene, 4000, true);
+        assert!(
+            ((l - reference) / reference).abs().max_element() < 0.03,
+            "{:?} {:?}",
+            l,
+            reference
+        );
+
+        // Shadowed by an opaque sphere
+        scene
+            .spheres
+            .push(geometry::Sphere::new(Vec3::new(0.0, 2.5, 0.0), 1.0, 0));
+        assert_eq!(direct(&scene, 1000, true), Vec3::zero());
+    }
+
+    #[test]
+    fn test_sampler() {
+        use crate::scene::Sampler::Halton;
+
+        for &kind in [Independent, Halton, Sobol].iter() {
+            let mut mean = [0.0; 6];
+            for i in 0..4096 {
+                let mut rng = rng(kind, i);
+                rng.next_1d();
+                let s = [rng.next_3d(), rng.next_3d()];
+                let s = [s[0].x(), s[0].y(), s[0].z(), s[1].x(), s[1].y(), s[1].z()];
+                for (m, x) in mean.iter_mut().zip(s.iter()) {
+                    assert!((0.0..1.0).contains(x));
+                    *m += x / 4096.0;
+                }
+            }
+            for m in mean.iter() {
+                assert!((m - 0.5).abs() < 0.02, "{:?} {:?}", kind, mean);
+            }
+        }
+
+        // 256 Sobol samples of a 2D pair fall once in each cell of a 16 x 16
+        // grid, and once in each of 256 intervals in 1D
+        let mut cells = [0; 256];
+        let mut intervals = [0; 256];
+        for i in 0..256 {
+            let mut rng = rng(Sobol, i);
+            rng.next_1d();
+            let s = rng.next_2d();
+            cells[(s.y() * 16.0) as usize * 16 + (s.x() * 16.0) as usize] += 1;
+            intervals[(rng.next_1d() * 256.0) as usize] += 1;
+        }
+        assert!(cells.iter().chain(intervals.iter()).all(|&c| c == 1));
+
+        // The first Halton dimension is the base 2 radical inverse
+        let mut intervals = [0; 256];
+        for i in 0..256 {
+            intervals[(rng(Halton, i).next_1d() * 256.0) as usize] += 1;
+        }
+        assert!(intervals.iter().all(|&c| c == 1));
+
+        // The tracking stream leaves the dimensions of the sample alone
+        let mut tracked = rng(Sobol, 5);
+        let s = tracked.next_tracking_2d();
+        assert_ne!(s.x(), s.y());
+        assert_ne!(tracked.next_tracking_1d(), s.x());
+        assert_eq!(tracked.next_3d(), rng(Sobol, 5).next_3d());
+    }
+
+    #[test]
+    fn test_media() {
+        let n = 100_000;
+        let samples = (0..n).map(|i| rng(Sobol, i).next_2d());
+
+        // The mean cosine of the phase function is its anisotropy
+        for &g in [-0.5, 0.0, 0.8].iter() {
+            let d = Vec3::new(0.0, 0.6, 0.8);
+            let mean: f32 = samples
+                .clone()
+                .map(|s| sample_hg(d, g, s).dot(d))
+                .sum::<f32>()
+                / n as f32;
+            assert!((mean - g).abs() < 0.01, "{} {}", g, mean);
+        }
+
+        // Without absorption scattering and passing through carry all energy
+        // on average, whatever channel the distance is sampled in
+        let medium = Medium {
+            absorption: Vec3::zero(),
+            scattering: Vec3::new(0.5, 1.0, 2.0),
+            anisotropy: 0.0,
+        };
+        let mut total = Vec3::zero();
+        for s in samples {
+            let mut throughput = Vec3::one();
+            sample_medium(&medium, 1.0, s, &mut throughput);
+            total += throughput;
+        }
+        for c in 0..3 {
+            assert!((total[c] / n as f32 - 1.0).abs() < 0.02, "{:?}", total);
+        }
+
+        // Absorbing fog between the camera and the light
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, -1.0)),
+                materials: [
+                    (name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (color: (1.0, 1.0, 1.0))),
+                ],
+                primitives: [
+                    Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: "light"),
+                ],
+                fog: Some((scattering: (0.0, 0.0, 0.0), absorption: (0.1, 0.0, 0.0))),
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new(".")).unwrap();
+        let expected = [(-1.0f32).exp(), 1.0, 1.0];
+        for p in tracer(&scene, 4, 4).render(1) {
+            for c in 0..3 {
+                assert!((p[c] - expected[c]).abs() < 1e-4, "{:?}", p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_volume() {
+        // Density rising along z from 0 to 1 between the two voxel centers
+        let mut vol = b"VOL\x03".to_vec();
+        for i in [1i32, 1, 1, 2, 1].iter() {
+            vol.extend_from_slice(&i.to_le_bytes());
+        }
+        for f in [-1.0f32, -1.0, -1.0, 1.0, 1.0, 1.0, 0.0, 1.0].iter() {
+            vol.extend_from_slice(&f.to_le_bytes());
+        }
+        let dir = std::env::temp_dir().join(format!("volume-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ramp.vol"), &vol).unwrap();
+
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 0.0, 0.0), look_at: (0.0, 0.0, 1.0)),
+                materials: [
+                    (name: "light", albedo: (0.0, 0.0, 0.0), specular: 0.0, emission: (color: (1.0, 1.0, 1.0))),
+                    (name: "smoke", albedo: (1.0, 1.0, 1.0), absorption: (1.0, 2.0, 0.0)),
+                ],
+                primitives: [
+                    Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: "light"),
+                    Volume(path: "ramp.vol", material: "smoke"),
+                ],
+            )
+        "#;
+        let scene = Scene::parse(src, &dir);
+        // An emitter inside the box instead of around it
+        let inside = src.replace(
+            r#"Sphere(center: (0.0, 0.0, 0.0), radius: 10.0, material: "light")"#,
+            r#"Triangle(vertices: ((-1.0, -1.0, 0.5), (1.0, -1.0, 0.5), (0.0, 1.0, 0.5)), material: "light")"#,
+        );
+        let inside = Scene::parse(&inside, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let r = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::unit_z(),
+        };
+        let n = 4000;
+        let check = |scene: &Scene, expected: Vec3| {
+            let tracer = tracer(scene, 1, 1);
+            let mut total = Vec3::zero();
+            for i in 0..n {
+                total += tracer.ray_color(r, &mut rng(Sobol, i));
+            }
+            for c in 0..3 {
+                assert!(
+                    (total[c] / n as f32 - expected[c]).abs() < 0.01,
+                    "{:?}",
+                    total / n as f32
+                );
+            }
+        };
+
+        // Along +z from the center the optical depth per unit absorption is
+        // 2 * (0.25 * 0.75 + 0.25)
+        check(&scene.unwrap(), exp(-Vec3::new(0.875, 1.75, 0.0)));
+        // The surface at z = 0.5 doesn't leave the box but is seen through the
+        // grid as well, with an optical depth of 0.25 * 1.5
+        check(&inside.unwrap(), exp(-Vec3::new(0.375, 0.75, 0.0)));
+    }
+
+    #[test]
+    fn test_environment() {
+        let dir = std::env::temp_dir().join(format!("environment-scene-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = std::fs::File::create(dir.join("sky.hdr")).unwrap();
+        let sky = vec![image::Rgb([1.0, 0.5, 0.25]); 8];
+        image::hdr::HdrEncoder::new(file)
+            .encode(&sky, 4, 2)
+            .unwrap();
+
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 1.0, 0.0), look_at: (0.0, 1.0, -1.0), vfov: 1.0),
+                materials: [(name: "floor", albedo: (0.5, 0.5, 0.5), specular: 0.0)],
+                primitives: [
+                    Triangle(vertices: ((-10.0, 0.0, -10.0), (0.0, 0.0, 10.0), (10.0, 0.0, -10.0)), material: "floor"),
+                ],
+                environment: Some((path: "sky.hdr", intensity: 2.0)),
+            )
+        "#;
+        let scene = Scene::parse(src, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut scene = scene.unwrap();
+
+        // Rays that leave the scene see the environment as is
+        let floor = scene.triangles.clone();
+        scene.triangles.clear();
+        scene.spheres = vec![geometry::Sphere::new(Vec3::new(0.0, 5.0, 0.0), 0.5, 0)];
+        for p in tracer(&scene, 4, 4).render(1) {
+            assert_eq!(p, [2.0, 1.0, 0.5]);
+        }
+
+        // Bounces off the floor that leave a scene without an environment or
+        // lights add nothing
+        scene.environment = None;
+        scene.triangles = floor.clone();
+        let t = tracer(&scene, 1, 1);
+        let r = Ray {
+            origin: Vec3::unit_y(),
+            direction: -Vec3::unit_y(),
+        };
+        for i in 0..16 {
+            assert_eq!(t.ray_color(r, &mut rng(Sobol, i)), Vec3::zero());
+        }
+        scene.triangles.clear();
+
+        // The center of the image faces -z, texel centers are exact
+        let texels = (1..9).map(|i| [i as f32; 3]).collect();
+        scene.environment = Some(Environment::from_rgb(4, 2, texels));
+        let t = tracer(&scene, 1, 1);
+        assert!((t.environment_uv(-Vec3::unit_z()) - Vec2::new(0.5, 0.5)).length() < 1e-6);
+        assert!((t.environment_uv(Vec3::unit_x()) - Vec2::new(0.75, 0.5)).length() < 1e-6);
+        let texel_center = |x: f32, y: f32| Vec2::new((x + 0.5) / 4.0, (y + 0.5) / 2.0);
+        let d = t.environment_direction(texel_center(2.0, 1.0));
+        assert!(
+            (t.environment_radiance(d) - Vec3::splat(7.0))
+                .abs()
+                .max_element()
+                < 1e-4
+        );
+
+        // Turning it a quarter moves every texel by one column
+        scene.environment = scene.environment.map(|e| e.with_rotation(90.0));
+        let t = tracer(&scene, 1, 1);
+        assert!(
+            (t.environment_radiance(d) - Vec3::splat(6.0))
+                .abs()
+                .max_element()
+                < 1e-4
+        );
+
+        // The density integrates to one and importance sampling converges to
+        // the radiance integrated over the sphere
+        let n = 400;
+        let (mut pdf_sum, mut integral) = (0.0, Vec3::zero());
+        for j in 0..n * n {
+            let theta = PI * ((j / n) as f32 + 0.5) / n as f32;
+            let phi = 2.0 * PI * ((j % n) as f32 + 0.5) / n as f32;
+            let d = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let d_omega = theta.sin() * 2.0 * PI * PI / (n * n) as f32;
+            pdf_sum += t.environment_pdf(d) * d_omega;
+            integral += t.environment_radiance(d) * d_omega;
+        }
+        assert!((pdf_sum - 1.0).abs() < 0.01, "{}", pdf_sum);
+
+        let samples = 100_000;
+        let mut estimate = Vec3::zero();
+        for i in 0..samples {
+            let (d, pdf) = t.sample_environment(rng(Sobol, i).next_2d()).unwrap();
+            assert!(pdf > 0.0);
+            estimate += t.environment_radiance(d) / pdf;
+        }
+        let estimate = estimate / samples as f32;
+        assert!(
+            ((estimate - integral) / integral).abs().max_element() < 0.01,
+            "{:?} {:?}",
+            estimate,
+            integral
+        );
+
+        // A matte floor under a bright patch converges to what BSDF sampling
+        // finds by itself
+        scene.triangles = floor;
+        let mut texels = vec![[0.1; 3]; 16 * 8];
+        texels[2 * 16 + 5] = [50.0, 40.0, 30.0];
+        scene.environment = Some(Environment::from_rgb(16, 8, texels));
+        let direct = |n: u32, mis: bool| {
+            let tracer = tracer(&scene, 1, 1);
+            let r = Ray {
+                origin: Vec3::unit_y(),
+                direction: -Vec3::unit_y(),
+            };
+            let rec = tracer.hit_world(&r, 0.001, f32::MAX).unwrap();
+            let material = tracer.material(rec.mat_ptr);
+            let wo = Vec3::unit_y();
+            let mut sum = Vec3::zero();
+            for i in 0..n {
+                let mut rng = rng(Sobol, i);
+                if mis {
+                    sum += tracer.sample_direct(&material, &rec, wo, None, &mut rng);
+                }
+                let (f, wi, pdf) = match bsdf_sample(&material, &rec, wo, rng.next_3d()) {
+                    Some(sample) if sample.2 > 0.0 => sample,
+                    _ => continue,
+                };
+                let le = tracer.escaped_radiance(wi, pdf * mis as u32 as f32);
+                sum += f * wi.dot(rec.normal).abs() / pdf * le;
+            }
+            sum / n as f32
+        };
+        let reference = direct(200_000, false);
+        let l = direct(4000, true);
+        assert!(
+            ((l - reference) / reference).abs().max_element() < 0.03,
+            "{:?} {:?}",
+            l,
+            reference
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+
+use crate::material::luminance;
+use crate::traits::AsBytes;
+
+// Words before the texels in the buffer
+const HEADER_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Image(PathBuf, image::ImageError),
+    Exr(PathBuf, exr::error::Error),
+    UnsupportedFormat(PathBuf),
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvironmentError::Image(path, e) => write!(f, "{}: {}", path.display(), e),
+            EnvironmentError::Exr(path, e) => write!(f, "{}: {}", path.display(), e),
+            EnvironmentError::UnsupportedFormat(path) => write!(
+                f,
+                "{}: unsupported environment format, use .hdr or .exr",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {}
+
+/// Equirectangular image of the radiance reaching the scene from far away.
+/// The center of the image faces -z with +y up.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    /// Linear RGB texels, rows from the top.
+    pub texels: Vec<[f32; 3]>,
+    /// Turn around +y in degrees.
+    pub rotation: f32,
+    /// Scales every texel and the sun.
+    pub intensity: f32,
+    pub sun: Option<Sun>,
+}
+
+/// Disk of a distant light on top of the image, sampled on its own. Its
+/// direction is not turned by the environment's rotation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sun {
+    /// Towards the center of the disk.
+    pub direction: Vec3,
+    /// Half of the apparent diameter in radians.
+    pub angular_radius: f32,
+    pub radiance: [f32; 3],
+}
+
+impl Environment {
+    /// Loads a Radiance `.hdr` or OpenEXR image.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EnvironmentError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("hdr") => {
+                load_hdr(path).map_err(|e| EnvironmentError::Image(path.to_path_buf(), e))
+            }
+            Some("exr") => load_exr(path).map_err(|e| EnvironmentError::Exr(path.to_path_buf(), e)),
+            _ => Err(EnvironmentError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    pub fn from_rgb(width: u32, height: u32, texels: Vec<[f32; 3]>) -> Self {
+        Environment {
+            width,
+            height,
+            texels,
+            rotation: 0.0,
+            intensity: 1.0,
+            sun: None,
+        }
+    }
+
+    pub fn with_rotation(self, rotation: f32) -> Self {
+        Environment { rotation, ..self }
+    }
+
+    pub fn with_intensity(self, intensity: f32) -> Self {
+        Environment {
+            intensity: intensity.max(0.0),
+            ..self
+        }
+    }
+
+    pub fn with_sun(self, sun: Sun) -> Self {
+        Environment {
+            sun: Some(sun),
+            ..self
+        }
+    }
+
+    /// Cumulative weights of the rows, then of the texels in each row. Texels
+    /// are weighted by their luminance and the solid angle of their row.
+    fn distribution(&self) -> (Vec<f32>, Vec<f32>) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut marginal = Vec::with_capacity(height);
+        let mut conditional = Vec::with_capacity(width * height);
+
+        let mut total = 0.0f64;
+        for (y, row) in self.texels.chunks_exact(width.max(1)).enumerate() {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let mut sum = 0.0f64;
+            for c in row {
+                sum += (luminance(*c).max(0.0) * sin_theta) as f64;
+                conditional.push(sum as f32);
+            }
+            total += sum;
+            marginal.push(total as f32);
+        }
+        (marginal, conditional)
+    }
+}
+
+fn load_hdr(path: &Path) -> Result<Environment, image::ImageError> {
+    let file = File::open(path).map_err(image::ImageError::IoError)?;
+    let decoder = image::hdr::HdrDecoder::new(BufReader::new(file))?;
+    let metadata = decoder.metadata();
+    let texels = decoder.read_image_hdr()?.into_iter().map(|p| p.0).collect();
+    Ok(Environment::from_rgb(
+        metadata.width,
+        metadata.height,
+        texels,
+    ))
+}
+
+fn load_exr(path: &Path) -> Result<Environment, exr::error::Error> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![[0.0; 3]; resolution.area()]),
+        |(width, texels): &mut (usize, Vec<[f32; 3]>),
+         position,
+         (r, g, b, _): (f32, f32, f32, f32)| {
+            texels[position.y() * *width + position.x()] = [r, g, b];
+        },
+    )?;
+    let (width, texels) = image.layer_data.channel_data.pixels;
+    let height = texels.len() / width.max(1);
+    Ok(Environment::from_rgb(width as u32, height as u32, texels))
+}
+
+// One u32 array: width, height, intensity and rotation in turns, the sun's
+// direction, 1 - cos of its angular radius (zero without a sun), radiance and
+// a padding word, then the texels as RGB floats, then the distribution. Width
+// and height are zero without an environment.
+impl AsBytes for Option<Environment> {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut words: Vec<u32> = Vec::with_capacity(self.bytes_size() / 4);
+        match self {
+            None => words.extend_from_slice(&[0; HEADER_SIZE]),
+            Some(e) => {
+                words.extend_from_slice(&[
+                    e.width,
+                    e.height,
+                    e.intensity.to_bits(),
+                    (e.rotation / 360.0).to_bits(),
+                ]);
+                let (direction, cap, radiance) = match e.sun {
+                    // Without cancellation for tiny disks
+                    Some(s) => {
+                        let half = (0.5 * s.angular_radius).sin();
+                        (s.direction.normalize(), 2.0 * half * half, s.radiance)
+                    }
+                    None => (Vec3::unit_y(), 0.0, [0.0; 3]),
+                };
+                words.extend(
+                    [
+                        direction.x(),
+                        direction.y(),
+                        direction.z(),
+                        cap,
+                        radiance[0],
+                        radiance[1],
+                        radiance[2],
+                        0.0,
+                    ]
+                    .iter()
+                    .map(|f| f.to_bits()),
+                );
+                words.extend(e.texels.iter().flatten().map(|c| c.to_bits()));
+                let (marginal, conditional) = e.distribution();
+                words.extend(
+                    marginal
+                        .iter()
+                        .chain(conditional.iter())
+                        .map(|w| w.to_bits()),
+                );
+            }
+        }
+
+        bytemuck::cast_slice(&words).to_vec()
+    }
+
+    fn bytes_size(&self) -> usize {
+        let data = self
+            .as_ref()
+            .map_or(0, |e| 4 * e.texels.len() + e.height as usize);
+        4 * (HEADER_SIZE + data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_bytes() {
+        assert_eq!(None::<Environment>.as_bytes(), vec![0; 48]);
+
+        let environment = Some(
+            Environment::from_rgb(2, 2, vec![[1.0; 3], [3.0; 3], [0.0, 1.0, 0.0], [-1.0; 3]])
+                .with_rotation(90.0)
+                .with_intensity(2.0)
+                .with_sun(Sun {
+                    direction: Vec3::new(0.0, 2.0, 0.0),
+                    angular_radius: 0.5,
+                    radiance: [10.0, 20.0, 30.0],
+                }),
+        );
+
+        let bytes = environment.as_bytes();
+        assert_eq!(bytes.len(), environment.bytes_size());
+
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|w| f32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        assert_eq!(&bytes[0..8], &[2, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&floats[2..4], &[2.0, 0.25]);
+        assert_eq!(&floats[4..7], &[0.0, 1.0, 0.0]);
+        assert!((floats[7] - (1.0 - 0.5f32.cos())).abs() < 1e-6);
+        assert_eq!(&floats[8..11], &[10.0, 20.0, 30.0]);
+        assert_eq!(&floats[12..15], &[1.0; 3]);
+        assert_eq!(&floats[21..24], &[-1.0; 3]);
+
+        // Both rows cover the same solid angle, negative texels are never
+        // picked
+        let sin = (PI / 4.0).sin();
+        let expected = [
+            4.0 * sin,
+            4.7152 * sin,
+            sin,
+            4.0 * sin,
+            0.7152 * sin,
+            0.7152 * sin,
+        ];
+        for (f, e) in floats[24..].iter().zip(expected.iter()) {
+            assert!((f - e).abs() < 1e-5, "{:?}", &floats[24..]);
+        }
+        assert_eq!(floats.len(), 30);
+    }
+
+    #[test]
+    fn test_load() {
+        // Powers of two survive the shared exponent of .hdr texels
+        let texels = vec![[1.0, 0.5, 0.25], [2.0, 4.0, 0.5], [0.0; 3], [8.0, 8.0, 8.0]];
+        let dir = std::env::temp_dir().join(format!("environment-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let file = File::create(dir.join("sky.hdr")).unwrap();
+        let rgb: Vec<image::Rgb<f32>> = texels.iter().map(|c| image::Rgb(*c)).collect();
+        image::hdr::HdrEncoder::new(file)
+            .encode(&rgb, 2, 2)
+            .unwrap();
+        exr::prelude::write_rgb_file(dir.join("sky.exr"), 2, 2, |x, y| {
+            let c = texels[y * 2 + x];
+            (c[0], c[1], c[2])
+        })
+        .unwrap();
+
+        let hdr = Environment::load(dir.join("sky.hdr"));
+        let exr = Environment::load(dir.join("sky.exr"));
+        let png = Environment::load(dir.join("sky.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for e in [hdr.unwrap(), exr.unwrap()].iter() {
+            assert_eq!((e.width, e.height), (2, 2));
+            assert_eq!(e.texels, texels);
+            assert_eq!((e.rotation, e.intensity), (0.0, 1.0));
+        }
+        assert!(matches!(png, Err(EnvironmentError::UnsupportedFormat(_))));
+    }
+}
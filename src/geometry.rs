@@ -1,6 +1,9 @@
 use crate::aabb::{Bounded, AABB};
 use crate::traits::AsBytes;
-use glam::Vec3;
+use glam::{Mat4, Vec2, Vec3};
+
+/// Light index of spheres that are not sampled as lights.
+pub const NO_LIGHT: u32 = 0xFFFFFFFF;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default)]
@@ -8,7 +11,9 @@ pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
     pub mat_index: u32,
-    pub pad0: [f32; 2],
+    /// Index into the light list of emissive spheres, or `NO_LIGHT`.
+    pub light_index: u32,
+    pub pad0: f32,
     pub esc_index: u32,
 }
 unsafe impl bytemuck::Pod for Sphere {}
@@ -20,10 +25,15 @@ impl Sphere {
             center,
             radius,
             mat_index,
-            pad0: [0.0; 2],
+            light_index: NO_LIGHT,
+            pad0: 0.0,
             esc_index: 0,
         }
     }
+
+    pub fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
 }
 
 impl Bounded for Sphere {
@@ -53,3 +63,190 @@ impl AsBytes for Vec<Sphere> {
         (std::mem::size_of::<Sphere>() + 12) * self.len() + 16
     }
 }
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    pub normals: [Vec3; 3],
+    /// Texture coordinates, (0, 0) is the top left of an image.
+    pub uvs: [Vec2; 3],
+    pub mat_index: u32,
+    pub esc_index: u32,
+}
+unsafe impl bytemuck::Pod for Triangle {}
+unsafe impl bytemuck::Zeroable for Triangle {}
+
+impl Triangle {
+    /// Flat shaded triangle, normals follow the counter-clockwise winding.
+    pub fn new(vertices: [Vec3; 3], mat_index: u32) -> Self {
+        let n = (vertices[1] - vertices[0])
+            .cross(vertices[2] - vertices[0])
+            .normalize();
+        Self::with_normals(vertices, [n; 3], mat_index)
+    }
+
+    pub fn with_normals(vertices: [Vec3; 3], normals: [Vec3; 3], mat_index: u32) -> Self {
+        Triangle {
+            vertices,
+            normals,
+            uvs: [Vec2::zero(); 3],
+            mat_index,
+            esc_index: 0,
+        }
+    }
+
+    pub fn with_uvs(self, uvs: [Vec2; 3]) -> Self {
+        Triangle { uvs, ..self }
+    }
+
+    pub fn transform(&self, m: &Mat4) -> Self {
+        let normal_matrix = m.inverse().transpose();
+        let v = |i: usize| m.transform_point3(self.vertices[i]);
+        let n = |i: usize| normal_matrix.transform_vector3(self.normals[i]).normalize();
+        Triangle {
+            vertices: [v(0), v(1), v(2)],
+            normals: [n(0), n(1), n(2)],
+            ..*self
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        let [a, b, c] = self.vertices;
+        0.5 * (b - a).cross(c - a).length()
+    }
+}
+
+impl Bounded for Triangle {
+    fn get_bounds(&self) -> AABB {
+        // Pad so axis aligned triangles don't produce flat boxes which the
+        // slab test in the shader can never hit.
+        let pad = Vec3::splat(1e-4);
+        let [a, b, c] = self.vertices;
+        AABB {
+            min: a.min(b).min(c) - pad,
+            max: a.max(b).max(c) + pad,
+        }
+    }
+}
+
+impl AsBytes for Vec<Triangle> {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut flat: Vec<u8> = Vec::new();
+
+        flat.extend_from_slice(bytemuck::cast_slice(&[self.len() as u32])); // 0
+        flat.extend_from_slice(bytemuck::cast_slice(&[0u32; 3])); // 1, 2, 3
+        for t in self.iter() {
+            for (i, v) in t.vertices.iter().enumerate() {
+                flat.extend_from_slice(bytemuck::cast_slice(v.as_ref()));
+                match i {
+                    0 => flat.extend_from_slice(bytemuck::cast_slice(&[t.mat_index])),
+                    _ => flat.extend_from_slice(bytemuck::cast_slice(&[0u32])),
+                }
+            }
+            for n in t.normals.iter() {
+                flat.extend_from_slice(bytemuck::cast_slice(n.as_ref()));
+                flat.extend_from_slice(bytemuck::cast_slice(&[0u32]));
+            }
+            for uv in t.uvs.iter() {
+                flat.extend_from_slice(bytemuck::cast_slice(uv.as_ref()));
+            }
+            flat.extend_from_slice(bytemuck::cast_slice(&[0u32; 2]));
+        }
+
+        flat
+    }
+
+    fn bytes_size(&self) -> usize {
+        8 * 16 * self.len() + 16
+    }
+}
+
+/// Bounds the delta tracking steps per segment through a volume, paths still
+/// inside after that many are absorbed. Same as in medium.glsl.
+pub const MAX_TRACKING_STEPS: u32 = 256;
+
+/// Axis aligned box filled with a heterogeneous medium. The material's
+/// absorption and scattering are scaled by the `density` grid texture.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Volume {
+    pub min: Vec3,
+    pub mat_index: u32,
+    pub max: Vec3,
+    pub density: u32,
+    pub esc_index: u32,
+}
+unsafe impl bytemuck::Pod for Volume {}
+unsafe impl bytemuck::Zeroable for Volume {}
+
+impl Volume {
+    pub fn new(min: Vec3, max: Vec3, density: u32, mat_index: u32) -> Self {
+        Volume {
+            min: min.min(max),
+            mat_index,
+            max: min.max(max),
+            density,
+            esc_index: 0,
+        }
+    }
+}
+
+impl Bounded for Volume {
+    fn get_bounds(&self) -> AABB {
+        AABB {
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+impl AsBytes for Vec<Volume> {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut flat: Vec<u8> = Vec::new();
+
+        flat.extend_from_slice(bytemuck::cast_slice(&[self.len() as u32, 0, 0, 0]));
+        for v in self.iter() {
+            flat.extend_from_slice(bytemuck::cast_slice(v.min.as_ref()));
+            flat.extend_from_slice(bytemuck::cast_slice(&[v.mat_index]));
+            flat.extend_from_slice(bytemuck::cast_slice(v.max.as_ref()));
+            flat.extend_from_slice(bytemuck::cast_slice(&[v.density]));
+        }
+
+        flat
+    }
+
+    fn bytes_size(&self) -> usize {
+        2 * 16 * self.len() + 16
+    }
+}
+
+/// Emissive sphere sampled towards its visible cap by next event estimation.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Light {
+    pub center: Vec3,
+    pub radius: f32,
+    pub mat_index: u32,
+}
+unsafe impl bytemuck::Pod for Light {}
+unsafe impl bytemuck::Zeroable for Light {}
+
+impl AsBytes for Vec<Light> {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut flat: Vec<u8> = Vec::new();
+
+        flat.extend_from_slice(bytemuck::cast_slice(&[self.len() as u32, 0, 0, 0]));
+        for l in self.iter() {
+            flat.extend_from_slice(bytemuck::cast_slice(l.center.as_ref()));
+            flat.extend_from_slice(bytemuck::cast_slice(&[l.radius]));
+            flat.extend_from_slice(bytemuck::cast_slice(&[l.mat_index, 0, 0, 0]));
+        }
+
+        flat
+    }
+
+    fn bytes_size(&self) -> usize {
+        2 * 16 * self.len() + 16
+    }
+}
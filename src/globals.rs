@@ -13,6 +13,17 @@ pub struct Globals {
     pub window_size: Vec2,
     pub rng_seed: f32,
     pub num_frames: u32,
+    /// Homogeneous medium outside of all volumes, see `Fog`.
+    pub fog_scattering: Vec3,
+    pub fog_anisotropy: f32,
+    pub fog_absorption: Vec3,
+    /// Bounces per path, Russian roulette may end paths after
+    /// `roulette_depth` of them.
+    pub max_depth: u32,
+    pub roulette_depth: u32,
+    /// `Sampler` as its discriminant.
+    pub sampler_kind: u32,
+    pub pad0: [u32; 2],
 }
 unsafe impl bytemuck::Pod for Globals {}
 unsafe impl bytemuck::Zeroable for Globals {}
@@ -1 +1,315 @@
+// Principled BSDF after Burley 2012/2015: a diffuse and sheen base, a GGX
+// specular lobe shared by metals and opaque dielectrics, rough glass and a
+// GTR1 clearcoat. Directions point away from the surface and, inside this
+// file, are in a frame where the shading normal is +z.
 
+// Keeps the GGX distribution from collapsing into a delta
+#define MIN_ALPHA 0.001
+
+float luminance(vec3 c) {
+    return dot(c, vec3(0.2126, 0.7152, 0.0722));
+}
+
+float schlick_weight(float cos_theta) {
+    float m = clamp(1.0 - cos_theta, 0.0, 1.0);
+    return m * m * m * m * m;
+}
+
+// Unpolarized reflectance of a dielectric interface, eta is the index on the
+// far side over the index on the near side
+float fresnel_dielectric(float cos_i, float eta) {
+    // Index matched, also avoids rounding noise below
+    if (eta == 1.0) {
+        return 0.0;
+    }
+    float sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if (sin2_t >= 1.0) {
+        return 1.0;
+    }
+    float cos_t = sqrt(1.0 - sin2_t);
+    float rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    float rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// Orthonormal basis around n, Duff et al. 2017
+void onb(vec3 n, out vec3 t, out vec3 b) {
+    float s = n.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (s + n.z);
+    float c = n.x * n.y * a;
+    t = vec3(1.0 + s * n.x * n.x * a, s * c, -s * n.x);
+    b = vec3(c, s + n.y * n.y * a, -n.y);
+}
+
+// ---- Microfacet distributions ----
+
+float ggx_d(vec3 h, float alpha) {
+    float a2 = alpha * alpha;
+    // 1 - cos^2 from the tangent components keeps precision near the peak
+    float d = h.x * h.x + h.y * h.y + a2 * h.z * h.z;
+    return a2 / (M_PI * d * d);
+}
+
+float smith_lambda(vec3 v, float alpha) {
+    float tan2 = (v.x * v.x + v.y * v.y) / (v.z * v.z);
+    return 0.5 * (sqrt(1.0 + alpha * alpha * tan2) - 1.0);
+}
+
+float smith_g1(vec3 v, float alpha) {
+    return 1.0 / (1.0 + smith_lambda(v, alpha));
+}
+
+// Height correlated masking-shadowing
+float smith_g2(vec3 v, vec3 l, float alpha) {
+    return 1.0 / (1.0 + smith_lambda(v, alpha) + smith_lambda(l, alpha));
+}
+
+// Normals visible from v, Heitz 2018
+vec3 sample_ggx_vndf(vec3 v, float alpha, vec2 s) {
+    vec3 vh = normalize(vec3(alpha * v.x, alpha * v.y, v.z));
+    float len2 = vh.x * vh.x + vh.y * vh.y;
+    vec3 t1 = len2 > 0.0 ? vec3(-vh.y, vh.x, 0.0) * inversesqrt(len2) : vec3(1.0, 0.0, 0.0);
+    vec3 t2 = cross(vh, t1);
+
+    float r = sqrt(s.x);
+    float phi = M_TWO_PI * s.y;
+    float p1 = r * cos(phi);
+    float p2 = r * sin(phi);
+    float blend = 0.5 * (1.0 + vh.z);
+    p2 = (1.0 - blend) * sqrt(1.0 - p1 * p1) + blend * p2;
+
+    vec3 nh = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+    return normalize(vec3(alpha * nh.x, alpha * nh.y, max(0.0, nh.z)));
+}
+
+// Density of sample_ggx_vndf over half vectors
+float ggx_vndf_pdf(vec3 v, vec3 h, float alpha) {
+    return smith_g1(v, alpha) * max(dot(v, h), 0.0) * ggx_d(h, alpha) / v.z;
+}
+
+float gtr1_d(vec3 h, float alpha) {
+    float a2 = alpha * alpha;
+    return (a2 - 1.0) / (M_PI * log(a2) * (1.0 + (a2 - 1.0) * h.z * h.z));
+}
+
+vec3 sample_gtr1(float alpha, vec2 s) {
+    float a2 = alpha * alpha;
+    float cos_theta = sqrt(max(0.0, (1.0 - pow(a2, 1.0 - s.x)) / (1.0 - a2)));
+    float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    float phi = M_TWO_PI * s.y;
+    return vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+vec3 sample_cosine_hemisphere(vec2 s) {
+    float r = sqrt(s.x);
+    float phi = M_TWO_PI * s.y;
+    return vec3(r * cos(phi), r * sin(phi), sqrt(max(0.0, 1.0 - s.x)));
+}
+
+// ---- Principled BSDF ----
+
+// Material after textures, in the local frame of one hit
+struct Bsdf {
+    Material mat;
+    float alpha;
+    // Relative index of refraction across the surface
+    float eta;
+    // Relative index of the opaque specular layer
+    float eta_specular;
+    // Lobe selection probabilities, see bsdf_init
+    float p_diffuse;
+    float p_specular;
+    float p_glass;
+    float p_clearcoat;
+};
+
+Bsdf bsdf_init(Material mat, vec3 wo, bool front_face) {
+    Bsdf b;
+    b.mat = mat;
+    b.alpha = max(mat.roughness * mat.roughness, MIN_ALPHA);
+    b.eta = front_face ? mat.ior : 1.0 / mat.ior;
+    float f0 = sqrt(clamp(0.08 * mat.specular, 0.0, 0.99));
+    b.eta_specular = (1.0 + f0) / (1.0 - f0);
+
+    // Weighted by the energy each lobe reflects towards wo
+    float m = mat.metallic;
+    float t = mat.transmission;
+    vec3 metal = mat.albedo + (1.0 - mat.albedo) * schlick_weight(wo.z);
+    float glass = fresnel_dielectric(wo.z, b.eta);
+    b.p_diffuse = (1.0 - m) * (1.0 - t) * (luminance(mat.albedo) + mat.sheen);
+    b.p_specular = m * luminance(metal) + (1.0 - m) * (1.0 - t) * fresnel_dielectric(wo.z, b.eta_specular);
+    b.p_glass = (1.0 - m) * t * (glass + (1.0 - glass) * luminance(mat.albedo));
+    b.p_clearcoat = 0.25 * mat.clearcoat * (0.04 + 0.96 * schlick_weight(wo.z));
+
+    float total = b.p_diffuse + b.p_specular + b.p_glass + b.p_clearcoat;
+    if (total > 0.0) {
+        b.p_diffuse /= total;
+        b.p_specular /= total;
+        b.p_glass /= total;
+        b.p_clearcoat /= total;
+    }
+    return b;
+}
+
+// BSDF value and the density bsdf_sample_local picks wi with
+vec3 bsdf_eval_local(Bsdf b, vec3 wo, vec3 wi, out float pdf) {
+    pdf = 0.0;
+    if (wi.z == 0.0) {
+        return vec3(0.0);
+    }
+
+    Material mat = b.mat;
+    float m = mat.metallic;
+    float t = mat.transmission;
+    vec3 f = vec3(0.0);
+
+    if (wi.z > 0.0) {
+        vec3 h = normalize(wo + wi);
+        float cos_d = dot(wi, h);
+
+        // Diffuse with retroreflection at grazing angles, and sheen
+        float fl = schlick_weight(wi.z);
+        float fv = schlick_weight(wo.z);
+        float fd90 = 0.5 + 2.0 * mat.roughness * cos_d * cos_d;
+        float retro = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        float lum = luminance(mat.albedo);
+        vec3 tint = lum > 0.0 ? mat.albedo / lum : vec3(1.0);
+        vec3 sheen = mat.sheen * mix(vec3(1.0), tint, mat.sheen_tint) * schlick_weight(cos_d);
+        f += (1.0 - m) * (1.0 - t) * (mat.albedo / M_PI * retro + sheen);
+        pdf += b.p_diffuse * wi.z / M_PI;
+
+        // Specular reflection of metals, opaque dielectrics and glass
+        float d = ggx_d(h, b.alpha);
+        float g = smith_g2(wo, wi, b.alpha);
+        float glass = fresnel_dielectric(cos_d, b.eta);
+        vec3 fresnel = m * (mat.albedo + (1.0 - mat.albedo) * schlick_weight(cos_d))
+            + (1.0 - m) * (1.0 - t) * fresnel_dielectric(cos_d, b.eta_specular)
+            + (1.0 - m) * t * glass;
+        f += fresnel * d * g / (4.0 * wi.z * wo.z);
+        float reflect_pdf = ggx_vndf_pdf(wo, h, b.alpha) / (4.0 * cos_d);
+        pdf += (b.p_specular + b.p_glass * glass) * reflect_pdf;
+
+        // Clearcoat, GTR1 with fixed roughness for shadowing
+        float alpha_c = mix(0.1, 0.001, mat.clearcoat_gloss);
+        float d_c = gtr1_d(h, alpha_c);
+        float f_c = 0.04 + 0.96 * schlick_weight(cos_d);
+        float g_c = smith_g2(wo, wi, 0.25);
+        f += vec3(0.25 * mat.clearcoat * d_c * f_c * g_c / (4.0 * wi.z * wo.z));
+        pdf += b.p_clearcoat * d_c * h.z / (4.0 * cos_d);
+    } else if (b.eta != 1.0) {
+        // Refraction, the half vector of Walter et al. 2007 on the side of wo
+        vec3 h = normalize(wo + b.eta * wi);
+        if (h.z < 0.0) {
+            h = -h;
+        }
+        float cos_o = dot(wo, h);
+        float cos_i = dot(wi, h);
+        if (cos_o <= 0.0 || cos_i >= 0.0) {
+            return vec3(0.0);
+        }
+
+        float denom = cos_o + b.eta * cos_i;
+        float glass = fresnel_dielectric(cos_o, b.eta);
+        float d = ggx_d(h, b.alpha);
+        float g = smith_g2(wo, wi, b.alpha);
+        // Leaves out the 1 / eta^2 scaling of radiance across the interface,
+        // it cancels out over closed objects
+        float jacobian = b.eta * b.eta * abs(cos_i) / (denom * denom);
+        f += (1.0 - m) * t * mat.albedo * (1.0 - glass) * d * g * cos_o * jacobian
+            / (abs(wi.z) * wo.z);
+        pdf += b.p_glass * (1.0 - glass) * ggx_vndf_pdf(wo, h, b.alpha) * jacobian;
+    }
+    return f;
+}
+
+// Picks a lobe, then a direction from it. Returns false without a sample.
+bool bsdf_sample_local(Bsdf b, vec3 wo, vec3 s, out vec3 wi) {
+    float x = s.x;
+    if (x < b.p_diffuse) {
+        wi = sample_cosine_hemisphere(s.yz);
+        return true;
+    }
+    x -= b.p_diffuse;
+    if (x < b.p_specular) {
+        wi = reflect(-wo, sample_ggx_vndf(wo, b.alpha, s.yz));
+        return wi.z > 0.0;
+    }
+    x -= b.p_specular;
+    if (x < b.p_glass) {
+        vec3 h = sample_ggx_vndf(wo, b.alpha, s.yz);
+        // Reuse the remainder of the lobe choice between reflection and refraction
+        if (x / b.p_glass < fresnel_dielectric(dot(wo, h), b.eta)) {
+            wi = reflect(-wo, h);
+            return wi.z > 0.0;
+        }
+        wi = b.eta == 1.0 ? -wo : refract(-wo, h, 1.0 / b.eta);
+        return wi.z < 0.0;
+    }
+    x -= b.p_glass;
+    if (x < b.p_clearcoat) {
+        vec3 h = sample_gtr1(mix(0.1, 0.001, b.mat.clearcoat_gloss), s.yz);
+        wi = reflect(-wo, h);
+        return wi.z > 0.0;
+    }
+    return false;
+}
+
+// ---- World space interface ----
+
+// Shading frame of a hit, wo is the direction back along the incoming ray
+struct Frame {
+    vec3 t;
+    vec3 b;
+    vec3 n;
+};
+
+Frame frame_at(vec3 n) {
+    Frame f;
+    f.n = n;
+    onb(n, f.t, f.b);
+    return f;
+}
+
+vec3 to_local(Frame f, vec3 v) {
+    return vec3(dot(v, f.t), dot(v, f.b), dot(v, f.n));
+}
+
+vec3 to_world(Frame f, vec3 v) {
+    return v.x * f.t + v.y * f.b + v.z * f.n;
+}
+
+// Interpolated normals can face away from the viewer
+vec3 local_wo(Frame f, vec3 wo) {
+    vec3 v = to_local(f, wo);
+    v.z = max(v.z, 1e-4);
+    return normalize(v);
+}
+
+vec3 bsdf_eval(Material mat, HitRec rec, vec3 wo, vec3 wi, out float pdf) {
+    Frame f = frame_at(rec.normal);
+    vec3 v = local_wo(f, wo);
+    Bsdf b = bsdf_init(mat, v, rec.front_face);
+    return bsdf_eval_local(b, v, to_local(f, wi), pdf);
+}
+
+// Samples wi, returns the BSDF value and its density. A zero pdf ends the path.
+vec3 bsdf_sample(Material mat, HitRec rec, vec3 wo, vec3 s, out vec3 wi, out float pdf) {
+    Frame f = frame_at(rec.normal);
+    vec3 v = local_wo(f, wo);
+    Bsdf b = bsdf_init(mat, v, rec.front_face);
+
+    vec3 l;
+    if (!bsdf_sample_local(b, v, s, l)) {
+        pdf = 0.0;
+        return vec3(0.0);
+    }
+    l = normalize(l);
+    wi = normalize(to_world(f, l));
+
+    // Index matched glass passes light straight through, a delta eval can't find
+    if (b.eta == 1.0 && l.z < 0.0) {
+        pdf = b.p_glass;
+        return (1.0 - mat.metallic) * mat.transmission * mat.albedo / abs(l.z);
+    }
+    return bsdf_eval_local(b, v, l, pdf);
+}
@@ -12,15 +12,53 @@ layout(set = 0, binding = 0, std140) uniform Globals {
     vec2 window_size;
     float seed;
     uint num_frames;
+
+    vec3 fog_scattering;
+    float fog_anisotropy;
+    vec3 fog_absorption;
+
+    uint max_depth;
+    uint roulette_depth;
+    // See sampler.glsl
+    uint sampler_kind;
 } globals;
 
 layout(set = 0, binding = 1, rgba32f) uniform image2D output_image;
 
-layout(set = 0, binding = 2, std140) uniform Materials {
+layout(set = 0, binding = 2, std140) readonly buffer Materials {
     uint len;
-    Material data[MAX_MATERIALS];
+    Material data[];
 } materials;
 
 layout(set = 0, binding = 3, std140) buffer BVH {
     vec4 nodes[];
 } bvh;
+
+layout(set = 0, binding = 4, std140) readonly buffer Triangles {
+    uint len;
+    vec4 data[];
+} triangles;
+
+// Texture table followed by the texels, see texture.rs
+layout(set = 0, binding = 5) readonly buffer Textures {
+    uint data[];
+} textures;
+
+layout(set = 0, binding = 6, std140) readonly buffer Volumes {
+    uint len;
+    Volume data[];
+} volumes;
+
+layout(set = 0, binding = 7, std140) readonly buffer Lights {
+    uint len;
+    Light data[];
+} lights;
+
+// Environment map and its sampling distribution, see environment.rs
+layout(set = 0, binding = 8) readonly buffer Environment {
+    uint data[];
+} environment;
+
+// Density grids stacked along z, see `Grid::atlas` in texture.rs
+layout(set = 0, binding = 9) uniform texture3D grids;
+layout(set = 0, binding = 10) uniform sampler grid_sampler;
@@ -1,4 +1,4 @@
-Ray get_ray(Camera c, float s, float t, uvec3 x) {
+Ray get_ray(Camera c, float s, float t, vec2 lens) {
     float theta = radians(c.vfov);
     float h = tan(theta / 2.0);
     float viewport_height = 2.0 * h;
@@ -12,7 +12,7 @@ Ray get_ray(Camera c, float s, float t, uvec3 x) {
     vec3 vertical = c.focus_dist * viewport_height * v;
     vec3 upper_left_corner = c.look_from - horizontal/2.0 + vertical/2.0 - c.focus_dist*w;
 
-    vec2 rd = 0.5 * c.aperture * random_in_unit_disk(x);
+    vec2 rd = 0.5 * c.aperture * random_in_unit_disk(lens);
     vec3 offset = u * rd.x + v * rd.y;
 
     vec3 ray_dir = normalize(upper_left_corner + s*horizontal - t*vertical - c.look_from - offset);
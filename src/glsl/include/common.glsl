@@ -1,11 +1,26 @@
 #define FLT_MAX 3.402823466e+38
 #define FLT_MIN 1.175494351e-38
+#define M_PI 3.14159265359
 #define M_TWO_PI 6.28318530718
 
 #define MAX_SPHERES 2048
-#define MAX_MATERIALS 2048
 #define MAX_NODES 2048
 
+// BVH element tags, see bvh.rs
+#define NODE_TYPE 0xFFFFFFFFu
+#define TRIANGLE_TYPE 0xFFFFFFFEu
+#define VOLUME_TYPE 0xFFFFFFFDu
+#define TRIANGLE_STRIDE 8
+
+// Texture index of materials without a texture, see texture.rs
+#define NO_TEXTURE 0xFFFFFFFFu
+
+// Volume index of surfaces
+#define NO_VOLUME 0xFFFFFFFFu
+
+// Light index of everything but emissive spheres, see geometry.rs
+#define NO_LIGHT 0xFFFFFFFFu
+
 const uint k = 1103515245U;
 
 vec3 hash3(uvec3 x) {
@@ -16,51 +31,19 @@ vec3 hash3(uvec3 x) {
     return vec3(x)*(1.0/float(0xffffffffU));
 }
 
-vec2 hash2(uvec3 x) {
-    return hash3(x).xy;
-}
-
-float hash(uvec3 x) {
-    return hash3(x).x;
-}
-
-vec2 rand2(uvec3 x) {
-    return (2.0 * hash2(x)) - 1.0;
-}
-
-float randf(uvec3 x, float min, float max) {
-    return min + (max-min) * hash(x);
-}
-
-vec3 sample_sphere_uniform(vec2 s) {
-    float phi = M_TWO_PI * s.x;
-    float cos_theta = 1.0 - 2.0 * s.y;
-    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
-
-    return vec3(cos(phi) * sin_theta, cos_theta, sin(phi) * sin_theta);
-}
-
-vec3 random_in_hemisphere(vec3 normal, vec2 s) {
-    vec3 in_unit_sphere = sample_sphere_uniform(s);
-    if (dot(in_unit_sphere, normal) > 0.0) { // In the same hemisphere as the normal
-        return in_unit_sphere;
-    } else {
-        return -in_unit_sphere;
-    }
-}
-
-vec2 random_in_unit_disk(uvec3 x) {
-    while (true) {
-        vec2 p = rand2(x);
-        if (p.x * p.x + p.y * p.y > 1) {
-            continue;
-        }
-        return p;
-    }
+// Uniform over the solid angle of a cone around +z, `one_minus_cos_max` is
+// passed as is so tiny cones keep their precision
+vec3 sample_cone(float one_minus_cos_max, vec2 s) {
+    float one_minus_cos = s.x * one_minus_cos_max;
+    float cos_theta = 1.0 - one_minus_cos;
+    float sin_theta = sqrt(max(0.0, one_minus_cos * (2.0 - one_minus_cos)));
+    float phi = M_TWO_PI * s.y;
+    return vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
 }
 
-float schlick(float cosine, float ref_idx) {
-    float r0 = (1-ref_idx) / (1+ref_idx);
-    r0 = r0*r0;
-    return r0 + (1-r0)*pow((1 - cosine), 5);
+// Polar mapping of `s`, every sample lands in the disk
+vec2 random_in_unit_disk(vec2 s) {
+    float r = sqrt(s.x);
+    float phi = M_TWO_PI * s.y;
+    return r * vec2(cos(phi), sin(phi));
 }
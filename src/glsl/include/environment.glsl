@@ -0,0 +1,172 @@
+// Equirectangular environment map and sun, see environment.rs for the buffer
+// layout and cpu.rs for the Rust port
+
+// Words before the texels
+#define ENVIRONMENT_HEADER 12u
+
+float environment_float(uint i) {
+    return uintBitsToFloat(environment.data[i]);
+}
+
+bool has_environment() {
+    return environment.data[0] > 0u;
+}
+
+vec3 environment_texel(uint x, uint y) {
+    uint i = ENVIRONMENT_HEADER + 3u * (y * environment.data[0] + x);
+    return vec3(environment_float(i), environment_float(i + 1u), environment_float(i + 2u));
+}
+
+// Image coordinates of direction `d`, u grows from -z towards +x
+vec2 environment_uv(vec3 d) {
+    float u = fract(0.5 + atan(d.x, -d.z) / M_TWO_PI - environment_float(3));
+    float v = acos(clamp(d.y, -1.0, 1.0)) / M_PI;
+    return vec2(u, v);
+}
+
+vec3 environment_direction(vec2 uv) {
+    float phi = M_TWO_PI * (uv.x + environment_float(3) - 0.5);
+    float theta = M_PI * uv.y;
+    return vec3(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+// Radiance arriving from direction `d`, bilinearly filtered with wrapping
+// longitudes
+vec3 environment_radiance(vec3 d) {
+    if (!has_environment()) {
+        return vec3(0.0);
+    }
+
+    uint width = environment.data[0];
+    uint height = environment.data[1];
+    vec2 p = environment_uv(d) * vec2(width, height) - 0.5;
+    vec2 f = fract(p);
+    ivec2 p0 = ivec2(floor(p));
+    uint x0 = uint(p0.x + int(width)) % width;
+    uint x1 = (x0 + 1u) % width;
+    uint y0 = uint(clamp(p0.y, 0, int(height) - 1));
+    uint y1 = uint(clamp(p0.y + 1, 0, int(height) - 1));
+
+    vec3 c = mix(
+        mix(environment_texel(x0, y0), environment_texel(x1, y0), f.x),
+        mix(environment_texel(x0, y1), environment_texel(x1, y1), f.x),
+        f.y);
+    return environment_float(2) * c;
+}
+
+// Density over solid angle of picking texel `x`, `y` and a point at latitude
+// `v` in it. Texels are picked proportionally to their luminance times the
+// sine of their row.
+float environment_texel_pdf(uint x, uint y, float v) {
+    uint width = environment.data[0];
+    uint height = environment.data[1];
+    float total = environment_float(ENVIRONMENT_HEADER + 3u * width * height + height - 1u);
+    float sin_theta = sin(M_PI * v);
+    if (total <= 0.0 || sin_theta <= 0.0) {
+        return 0.0;
+    }
+
+    float row_sin = sin(M_PI * (float(y) + 0.5) / float(height));
+    float weight = max(luminance(environment_texel(x, y)), 0.0) * row_sin;
+    return weight * float(width * height) / (total * 2.0 * M_PI * M_PI * sin_theta);
+}
+
+// Density of sample_environment over solid angle
+float environment_pdf(vec3 d) {
+    if (!has_environment()) {
+        return 0.0;
+    }
+
+    uint width = environment.data[0];
+    uint height = environment.data[1];
+    vec2 uv = environment_uv(d);
+    uint x = min(uint(uv.x * float(width)), width - 1u);
+    uint y = min(uint(uv.y * float(height)), height - 1u);
+    return environment_texel_pdf(x, y, uv.y);
+}
+
+// Picks one of the `n` cumulative weights from `offset` with `s` in [0, 1),
+// `f` is where `s` fell between the previous weight and the picked one
+uint sample_cumulative(uint offset, uint n, float s, out float f) {
+    float t = s * environment_float(offset + n - 1u);
+    uint lo = 0u;
+    uint hi = n - 1u;
+    while (lo < hi) {
+        uint mid = (lo + hi) / 2u;
+        if (environment_float(offset + mid) > t) {
+            hi = mid;
+        } else {
+            lo = mid + 1u;
+        }
+    }
+
+    float below = lo > 0u ? environment_float(offset + lo - 1u) : 0.0;
+    float size = environment_float(offset + lo) - below;
+    f = size > 0.0 ? clamp((t - below) / size, 0.0, 1.0) : 0.5;
+    return lo;
+}
+
+// Direction towards a texel picked by its row then by its column, the pdf is
+// zero without an environment or for a black one
+vec3 sample_environment(vec2 s, out float pdf) {
+    pdf = 0.0;
+    if (!has_environment()) {
+        return vec3(0.0);
+    }
+
+    uint width = environment.data[0];
+    uint height = environment.data[1];
+    uint marginal = ENVIRONMENT_HEADER + 3u * width * height;
+    if (environment_float(marginal + height - 1u) <= 0.0) {
+        return vec3(0.0);
+    }
+
+    float fx, fy;
+    uint y = sample_cumulative(marginal, height, s.x, fy);
+    uint x = sample_cumulative(marginal + height + y * width, width, s.y, fx);
+    vec2 uv = vec2((float(x) + fx) / float(width), (float(y) + fy) / float(height));
+    pdf = environment_texel_pdf(x, y, uv.y);
+    return environment_direction(uv);
+}
+
+// The sun is a disk of constant radiance, given by its direction and 1 - cos
+// of its angular radius
+bool has_sun() {
+    return has_environment() && environment_float(7) > 0.0;
+}
+
+vec3 sun_direction() {
+    return vec3(environment_float(4), environment_float(5), environment_float(6));
+}
+
+// Radiance of the sun seen in direction `d`, zero outside of its disk
+vec3 sun_radiance(vec3 d) {
+    if (!has_sun() || 1.0 - dot(d, sun_direction()) > environment_float(7)) {
+        return vec3(0.0);
+    }
+    return environment_float(2) * vec3(environment_float(8), environment_float(9), environment_float(10));
+}
+
+// Density of sample_sun over solid angle
+float sun_pdf(vec3 d) {
+    if (!has_sun() || 1.0 - dot(d, sun_direction()) > environment_float(7)) {
+        return 0.0;
+    }
+    return 1.0 / (M_TWO_PI * environment_float(7));
+}
+
+// Direction towards the disk of the sun, uniform in solid angle
+vec3 sample_sun(vec2 s, out float pdf) {
+    pdf = 0.0;
+    if (!has_sun()) {
+        return vec3(0.0);
+    }
+
+    float one_minus_cos_max = environment_float(7);
+    pdf = 1.0 / (M_TWO_PI * one_minus_cos_max);
+    vec3 w = sun_direction();
+    vec3 t, b;
+    onb(w, t, b);
+    vec3 c = sample_cone(one_minus_cos_max, s);
+    return normalize(c.x * t + c.y * b + c.z * w);
+}
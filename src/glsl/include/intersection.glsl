@@ -27,6 +27,147 @@ bool hit_sphere(Sphere s, Ray r, float t_min, float t_max, inout HitRec rec) {
     vec3 outward_normal = (rec.point - s.center) / s.radius;
     set_face_normal(rec, r, outward_normal);
     rec.mat_ptr = s.mat_ptr;
+    rec.volume = NO_VOLUME;
+    rec.light = s.light;
+    // Longitude around y and latitude from the north pole
+    vec3 n = outward_normal;
+    rec.uv = vec2(
+        atan(-n.z, n.x) / M_TWO_PI + 0.5,
+        acos(clamp(n.y, -1.0, 1.0)) / M_PI
+    );
+    float sin_theta = max(length(n.xz), 1e-4);
+    rec.dpdu = M_TWO_PI * s.radius * vec3(n.z, 0.0, -n.x);
+    rec.dpdv = M_PI * s.radius * vec3(n.x * n.y / sin_theta, -sin_theta, n.z * n.y / sin_theta);
+    return true;
+}
+
+Triangle load_triangle(uint index) {
+    uint base = TRIANGLE_STRIDE * index;
+    Triangle tri;
+    vec4 v0 = triangles.data[base + 0];
+    tri.v0 = v0.xyz;
+    tri.mat_ptr = floatBitsToUint(v0.w);
+    tri.v1 = triangles.data[base + 1].xyz;
+    tri.v2 = triangles.data[base + 2].xyz;
+    tri.n0 = triangles.data[base + 3].xyz;
+    tri.n1 = triangles.data[base + 4].xyz;
+    tri.n2 = triangles.data[base + 5].xyz;
+    vec4 uv01 = triangles.data[base + 6];
+    tri.uv0 = uv01.xy;
+    tri.uv1 = uv01.zw;
+    tri.uv2 = triangles.data[base + 7].xy;
+    return tri;
+}
+
+// Watertight ray/triangle intersection
+// http://jcgt.org/published/0002/01/05/paper.pdf
+bool hit_triangle(Triangle tri, Ray r, float t_min, float t_max, inout HitRec rec) {
+    // Permute axes so the ray direction's largest component is z
+    vec3 ad = abs(r.direction);
+    int kz = ad.x > ad.y ? (ad.x > ad.z ? 0 : 2) : (ad.y > ad.z ? 1 : 2);
+    int kx = (kz + 1) % 3;
+    int ky = (kx + 1) % 3;
+    if (r.direction[kz] < 0.0) {
+        int tmp = kx;
+        kx = ky;
+        ky = tmp;
+    }
+
+    // Shear so the ray points along +z
+    float sx = r.direction[kx] / r.direction[kz];
+    float sy = r.direction[ky] / r.direction[kz];
+    float sz = 1.0 / r.direction[kz];
+
+    vec3 a = tri.v0 - r.origin;
+    vec3 b = tri.v1 - r.origin;
+    vec3 c = tri.v2 - r.origin;
+
+    float ax = a[kx] - sx * a[kz];
+    float ay = a[ky] - sy * a[kz];
+    float bx = b[kx] - sx * b[kz];
+    float by = b[ky] - sy * b[kz];
+    float cx = c[kx] - sx * c[kz];
+    float cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentrics, edges shared by two triangles agree on the sign
+    float u = cx * by - cy * bx;
+    float v = ax * cy - ay * cx;
+    float w = bx * ay - by * ax;
+
+    if ((u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0)) {
+        return false;
+    }
+
+    float det = u + v + w;
+    if (det == 0.0) {
+        return false;
+    }
+
+    float t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    if (t > t_max || t < t_min) {
+        return false;
+    }
+
+    vec3 bary = vec3(u, v, w) / det;
+    vec3 geometric_normal = normalize(cross(tri.v1 - tri.v0, tri.v2 - tri.v0));
+    vec3 shading_normal = normalize(bary.x * tri.n0 + bary.y * tri.n1 + bary.z * tri.n2);
+
+    rec.t = t;
+    rec.point = ray_at(r, t);
+    rec.front_face = dot(r.direction, geometric_normal) < 0;
+    if (dot(shading_normal, geometric_normal) < 0.0) {
+        shading_normal = -shading_normal;
+    }
+    rec.normal = rec.front_face ? shading_normal : -shading_normal;
+    rec.mat_ptr = tri.mat_ptr;
+    rec.uv = bary.x * tri.uv0 + bary.y * tri.uv1 + bary.z * tri.uv2;
+    rec.volume = NO_VOLUME;
+    rec.light = NO_LIGHT;
+
+    // Tangents follow the texture coordinates, any frame without them
+    vec3 e1 = tri.v1 - tri.v0;
+    vec3 e2 = tri.v2 - tri.v0;
+    vec2 duv1 = tri.uv1 - tri.uv0;
+    vec2 duv2 = tri.uv2 - tri.uv0;
+    float uv_det = duv1.x * duv2.y - duv1.y * duv2.x;
+    if (abs(uv_det) > 1e-12) {
+        rec.dpdu = (duv2.y * e1 - duv1.y * e2) / uv_det;
+        rec.dpdv = (duv1.x * e2 - duv2.x * e1) / uv_det;
+    } else {
+        onb(geometric_normal, rec.dpdu, rec.dpdv);
+    }
+    return true;
+}
+
+// Volume boxes are hit where the ray enters them, or leaves them when it
+// starts inside. They have no surface of their own.
+bool hit_volume(uint index, Ray r, vec3 inv_dir, float t_min, float t_max, inout HitRec rec) {
+    Volume v = volumes.data[index];
+    vec3 tbot = inv_dir * (v.min - r.origin);
+    vec3 ttop = inv_dir * (v.max - r.origin);
+    vec3 tmin = min(ttop, tbot);
+    vec3 tmax = max(ttop, tbot);
+    float t0 = max(tmin.x, max(tmin.y, tmin.z));
+    float t1 = min(tmax.x, min(tmax.y, tmax.z));
+    if (t1 < t0) {
+        return false;
+    }
+
+    float t = t0 >= t_min ? t0 : t1;
+    if (t > t_max || t < t_min) {
+        return false;
+    }
+
+    rec.t = t;
+    rec.point = ray_at(r, t);
+    rec.front_face = t == t0;
+    rec.normal = -r.direction;
+    rec.mat_ptr = v.mat_ptr;
+    rec.uv = vec2(0.0);
+    rec.volume = index;
+    rec.light = NO_LIGHT;
+    rec.dpdu = vec3(0.0);
+    rec.dpdv = vec3(0.0);
     return true;
 }
 
@@ -58,11 +199,28 @@ bool hit_world(Ray r, float t_min, float t_max, inout HitRec rec) {
 
         uint shape_type = floatBitsToUint(node.min.w);
 
-        if (shape_type != 0xFFFFFFFF) { //Hit a shape
+        if (shape_type == TRIANGLE_TYPE) {
+            Triangle tri = load_triangle(floatBitsToUint(node.max.x));
+
+            if (hit_triangle(tri, r, t_min, closest_so_far, temp_rec)) {
+                hit_anything = true;
+                closest_so_far = temp_rec.t;
+                rec = temp_rec;
+            }
+
+        } else if (shape_type == VOLUME_TYPE) {
+            if (hit_volume(floatBitsToUint(node.max.x), r, inv_dir, t_min, closest_so_far, temp_rec)) {
+                hit_anything = true;
+                closest_so_far = temp_rec.t;
+                rec = temp_rec;
+            }
+
+        } else if (shape_type != NODE_TYPE) { //Hit a sphere
             Sphere s;
             s.center = node.min.xyz;
             s.radius = node.min.w;
             s.mat_ptr = floatBitsToUint(node.max.x);
+            s.light = floatBitsToUint(node.max.y);
             
             if (hit_sphere(s, r, t_min, closest_so_far, temp_rec)) {
                 hit_anything = true;
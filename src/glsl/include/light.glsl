@@ -0,0 +1,157 @@
+// Next event estimation towards emissive spheres, the environment and the
+// sun, see cpu.rs for the Rust port
+
+// Bounds the volume boxes a shadow ray passes through
+#define MAX_SHADOW_STEPS 8
+
+// Power heuristic with an exponent of two, Veach 1997. Weighs a sample of a
+// strategy with density `pdf` against one with density `other`, the balance
+// heuristic would leave the squares out.
+float mis_weight(float pdf, float other) {
+    float a = pdf * pdf;
+    float b = other * other;
+    return a / (a + b);
+}
+
+// 1 - cos of the cap of `l` visible from `p`, without cancellation for small
+// or distant lights. Zero from inside the light and from points on it, which
+// are left to BSDF sampling.
+float sphere_light_cap(Light l, vec3 p) {
+    vec3 w = l.center - p;
+    float sin2_max = l.radius * l.radius / dot(w, w);
+    if (sin2_max >= 0.998) {
+        return 0.0;
+    }
+    return sin2_max / (1.0 + sqrt(1.0 - sin2_max));
+}
+
+// Density of sample_sphere_light over solid angle
+float sphere_light_pdf(Light l, vec3 p) {
+    float cap = sphere_light_cap(l, p);
+    return cap > 0.0 ? 1.0 / (M_TWO_PI * cap) : 0.0;
+}
+
+// Direction from `p` towards the cap of `l` visible from it, uniform in solid
+// angle. The pdf is zero from inside the light.
+vec3 sample_sphere_light(Light l, vec3 p, vec2 s, out float pdf) {
+    float one_minus_cos_max = sphere_light_cap(l, p);
+    if (one_minus_cos_max <= 0.0) {
+        pdf = 0.0;
+        return vec3(0.0);
+    }
+
+    pdf = 1.0 / (M_TWO_PI * one_minus_cos_max);
+
+    vec3 w = normalize(l.center - p);
+    vec3 t, b;
+    onb(w, t, b);
+    vec3 c = sample_cone(one_minus_cos_max, s);
+    return normalize(c.x * t + c.y * b + c.z * w);
+}
+
+// Emissive spheres, then the environment and its sun if there are any
+uint light_count() {
+    return lights.len + (has_environment() ? 1u : 0u) + (has_sun() ? 1u : 0u);
+}
+
+// Radiance of the environment or the sun in direction `d` if light `index`
+// is one of them
+vec3 distant_radiance(uint index, vec3 d) {
+    if (index == lights.len) {
+        return environment_radiance(d);
+    }
+    return index == lights.len + 1u ? sun_radiance(d) : vec3(0.0);
+}
+
+// Emission of light `index` seen along `r` starting inside `volume`,
+// attenuated by the media on the way. Zero if any surface is in front of it.
+vec3 light_radiance(Ray r, uint index, uint volume, inout Sampler rng) {
+    vec3 tr = vec3(1.0);
+    HitRec rec;
+    for (uint i = 0; i < MAX_SHADOW_STEPS; i++) {
+        if (!hit_world(r, 0.001, FLT_MAX, rec)) {
+            Medium fog = global_fog();
+            return tr * exp(-(fog.absorption + fog.scattering) * FLT_MAX) * distant_radiance(index, r.direction);
+        }
+
+        bool in_volume;
+        Medium medium = segment_medium(rec, volume, in_volume);
+        if (in_volume) {
+            tr *= volume_transmittance(volumes.data[volume], medium, r, rec.t, rng);
+        } else {
+            tr *= exp(-(medium.absorption + medium.scattering) * rec.t);
+        }
+
+        if (rec.volume == NO_VOLUME) {
+            if (rec.light != index) {
+                return vec3(0.0);
+            }
+            Material m = materials.data[rec.mat_ptr];
+            return tr * m.emission * sample_texture(m.emission_texture, rec.uv, rec.point);
+        }
+        volume = rec.front_face ? rec.volume : NO_VOLUME;
+        r.origin = rec.point;
+    }
+    return vec3(0.0);
+}
+
+// Weight of the emission of `light` reached by a BSDF sample from `origin`
+// with density `bsdf_pdf`, which is zero where no light was sampled
+float light_hit_weight(uint light, vec3 origin, float bsdf_pdf) {
+    if (bsdf_pdf <= 0.0) {
+        return 1.0;
+    }
+    float light_pdf = sphere_light_pdf(lights.data[light], origin) / float(light_count());
+    return mis_weight(bsdf_pdf, light_pdf);
+}
+
+// Light of the environment and the sun reached by a BSDF sample leaving the
+// scene in direction `d`, each weighted against sampling it directly
+vec3 escaped_radiance(vec3 d, float bsdf_pdf) {
+    // Without an environment there may be no light to divide by
+    if (!has_environment()) {
+        return vec3(0.0);
+    }
+
+    vec3 env = environment_radiance(d);
+    vec3 sun = sun_radiance(d);
+    if (bsdf_pdf > 0.0) {
+        float count = float(light_count());
+        env *= mis_weight(bsdf_pdf, environment_pdf(d) / count);
+        sun *= mis_weight(bsdf_pdf, sun_pdf(d) / count);
+    }
+    return env + sun;
+}
+
+// Direct light from one light picked uniformly, weighted against finding it
+// by BSDF sampling. `volume` is the volume box around `rec`.
+vec3 sample_direct(Material mat, HitRec rec, vec3 wo, uint volume, inout Sampler rng) {
+    uint count = light_count();
+    if (count == 0u) {
+        return vec3(0.0);
+    }
+
+    vec3 s = next_3d(rng);
+    uint index = min(uint(s.x * float(count)), count - 1u);
+    float pdf;
+    vec3 wi;
+    if (index < lights.len) {
+        wi = sample_sphere_light(lights.data[index], rec.point, s.yz, pdf);
+    } else if (index == lights.len) {
+        wi = sample_environment(s.yz, pdf);
+    } else {
+        wi = sample_sun(s.yz, pdf);
+    }
+    if (pdf <= 0.0) {
+        return vec3(0.0);
+    }
+
+    float bsdf_pdf;
+    vec3 f = bsdf_eval(mat, rec, wo, wi, bsdf_pdf);
+    if (f == vec3(0.0)) {
+        return vec3(0.0);
+    }
+    vec3 le = light_radiance(Ray(rec.point, wi), index, volume, rng);
+    pdf /= float(count);
+    return f * abs(dot(wi, rec.normal)) * le * mis_weight(pdf, bsdf_pdf) / pdf;
+}
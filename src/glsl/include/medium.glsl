@@ -0,0 +1,156 @@
+// Homogeneous participating media, see cpu.rs for the Rust port
+
+Medium global_fog() {
+    return Medium(globals.fog_absorption, globals.fog_scattering, globals.fog_anisotropy);
+}
+
+// Volume box containing `p`, volumes are assumed not to overlap
+uint volume_at(vec3 p) {
+    for (uint i = 0; i < volumes.len; i++) {
+        Volume v = volumes.data[i];
+        if (all(greaterThan(p, v.min)) && all(lessThan(p, v.max))) {
+            return i;
+        }
+    }
+    return NO_VOLUME;
+}
+
+// Medium a ray inside `volume` went through before reaching `rec`: the inside
+// of the transmissive material it leaves, otherwise the volume or the fog.
+// `in_volume` is set when it is the medium of the volume's density grid.
+Medium segment_medium(HitRec rec, uint volume, out bool in_volume) {
+    in_volume = false;
+    if (!rec.front_face && rec.volume == NO_VOLUME) {
+        Material m = materials.data[rec.mat_ptr];
+        if (m.transmission > 0.0) {
+            return Medium(m.absorption, m.scattering, m.anisotropy);
+        }
+    }
+    if (volume != NO_VOLUME) {
+        in_volume = true;
+        Material m = materials.data[volumes.data[volume].mat_ptr];
+        return Medium(m.absorption, m.scattering, m.anisotropy);
+    }
+    return global_fog();
+}
+
+// Samples a free-flight distance in `m` before `t_max`. Distances are drawn
+// proportionally to the scattering of one color channel and averaged over the
+// channels, absorption is accounted for analytically. Returns true if the ray
+// scatters at `t`, in both cases `throughput` is weighted by transmittance
+// over density.
+bool sample_medium(Medium m, float t_max, vec2 s, out float t, inout vec3 throughput) {
+    t = t_max;
+    if (m.scattering == vec3(0.0)) {
+        throughput *= exp(-m.absorption * t_max);
+        return false;
+    }
+
+    int c = min(int(s.x * 3.0), 2);
+    if (m.scattering[c] > 0.0) {
+        t = min(-log(1.0 - s.y) / m.scattering[c], t_max);
+    }
+
+    vec3 tr = exp(-(m.absorption + m.scattering) * t);
+    vec3 density = exp(-m.scattering * t);
+    if (t < t_max) {
+        throughput *= m.scattering * tr / dot(m.scattering * density, vec3(1.0 / 3.0));
+        return true;
+    }
+    // Rays escaping to infinity are not weighted, nothing is behind them
+    if (t_max < FLT_MAX) {
+        throughput *= tr / dot(density, vec3(1.0 / 3.0));
+    }
+    return false;
+}
+
+// Samples the Henyey-Greenstein phase function around the propagation
+// `direction`, `g` > 0 scatters forward. The sample is exact so its weight is
+// one.
+vec3 sample_hg(vec3 direction, float g, vec2 s) {
+    float cos_theta;
+    if (abs(g) < 1e-3) {
+        cos_theta = 1.0 - 2.0 * s.x;
+    } else {
+        float sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * s.x);
+        cos_theta = (1.0 + g * g - sq * sq) / (2.0 * g);
+    }
+    cos_theta = clamp(cos_theta, -1.0, 1.0);
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    float phi = M_TWO_PI * s.y;
+
+    vec3 t, b;
+    onb(direction, t, b);
+    return normalize(sin_theta * cos(phi) * t + sin_theta * sin(phi) * b + cos_theta * direction);
+}
+
+// Bounds the work per segment in dense volumes, longer paths are absorbed.
+// Scenes that could reach it are rejected, see MAX_TRACKING_STEPS in
+// geometry.rs.
+#define MAX_TRACKING_STEPS 256
+
+// Delta tracking through the density grid of `v` with `m` per unit density.
+// Collisions against the majorant either scatter or are null, with weights
+// that account for absorption and the choice between the two. The first
+// collision uses `s`, the others the tracking stream of `rng`. Returns true
+// if the ray scatters at `t`, the throughput is zero if it is absorbed.
+bool sample_volume(Volume v, Medium m, Ray r, float t_max, vec2 s, inout Sampler rng, out float t, inout vec3 throughput) {
+    vec3 sigma_t = m.absorption + m.scattering;
+    float majorant = grid_majorant(v.density) * max(sigma_t.x, max(sigma_t.y, sigma_t.z));
+    t = 0.0;
+    if (majorant <= 0.0) {
+        return false;
+    }
+
+    for (uint i = 0; i < MAX_TRACKING_STEPS; i++) {
+        if (i > 0) {
+            s = next_tracking_2d(rng);
+        }
+        t -= log(1.0 - s.x) / majorant;
+        if (t >= t_max) {
+            return false;
+        }
+
+        float density = sample_grid(v.density, (ray_at(r, t) - v.min) / (v.max - v.min));
+        vec3 sigma_s = density * m.scattering;
+        vec3 sigma_n = majorant - density * sigma_t;
+        float p_s = dot(sigma_s, vec3(1.0 / 3.0));
+        float p_n = dot(sigma_n, vec3(1.0 / 3.0));
+        if (p_s + p_n <= 0.0) {
+            break;
+        }
+
+        p_s /= p_s + p_n;
+        if (s.y < p_s) {
+            throughput *= sigma_s / (majorant * p_s);
+            return true;
+        }
+        throughput *= sigma_n / (majorant * (1.0 - p_s));
+    }
+
+    throughput = vec3(0.0);
+    return false;
+}
+
+// Transmittance through the density grid of `v` up to `t_max`, estimated by
+// ratio tracking against the same majorant as sample_volume with the tracking
+// stream of `rng`
+vec3 volume_transmittance(Volume v, Medium m, Ray r, float t_max, inout Sampler rng) {
+    vec3 sigma_t = m.absorption + m.scattering;
+    float majorant = grid_majorant(v.density) * max(sigma_t.x, max(sigma_t.y, sigma_t.z));
+    vec3 tr = vec3(1.0);
+    if (majorant <= 0.0) {
+        return tr;
+    }
+
+    float t = 0.0;
+    for (uint i = 0; i < MAX_TRACKING_STEPS; i++) {
+        t -= log(1.0 - next_tracking_1d(rng)) / majorant;
+        if (t >= t_max) {
+            return tr;
+        }
+        float density = sample_grid(v.density, (ray_at(r, t) - v.min) / (v.max - v.min));
+        tr *= 1.0 - density * sigma_t / majorant;
+    }
+    return vec3(0.0);
+}
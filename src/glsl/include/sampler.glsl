@@ -0,0 +1,158 @@
+// Per pixel sample streams, see `Sampler` in scene.rs and cpu.rs for the Rust
+// port. Every draw takes the next dimensions of sample `index` of the pixel,
+// so the n-th number of a path lines up across the samples of a pixel. Loops
+// that draw a varying amount, like tracking through volumes, take numbers from
+// a separate independent stream that leaves the dimensions alone.
+
+#define SAMPLER_INDEPENDENT 0u
+#define SAMPLER_HALTON 1u
+#define SAMPLER_SOBOL 2u
+
+// Largest float below one
+#define ONE_MINUS_EPSILON 0.99999994
+
+// Dimensions with a Halton base, later ones are independent
+#define HALTON_DIMENSIONS 32u
+
+// First dimension of the tracking stream, far beyond any path
+#define TRACKING_DIMENSION 0x80000000u
+
+const uint HALTON_PRIMES[HALTON_DIMENSIONS] = uint[](
+    2u, 3u, 5u, 7u, 11u, 13u, 17u, 19u, 23u, 29u, 31u, 37u, 41u, 43u, 47u, 53u,
+    59u, 61u, 67u, 71u, 73u, 79u, 83u, 89u, 97u, 101u, 103u, 107u, 109u, 113u, 127u, 131u);
+
+struct Sampler {
+    uint kind;
+    // Hash of the pixel
+    uint seed;
+    uint index;
+    uint dimension;
+    // Numbers drawn from the tracking stream
+    uint tracking;
+};
+
+// PCG hash of Jarzynski and Olano
+uint pcg(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Top 24 bits as a float in [0, 1)
+float uint_to_float(uint x) {
+    return float(x >> 8u) * (1.0 / 16777216.0);
+}
+
+Sampler sampler_new(uint kind, uvec2 pixel, uint index) {
+    return Sampler(kind, pcg(pixel.x ^ pcg(pixel.y)), index, 0u, 0u);
+}
+
+uint hash_dimension(Sampler rng, uint dimension) {
+    return pcg(rng.seed ^ pcg(dimension));
+}
+
+float independent_1d(Sampler rng, uint dimension) {
+    return uint_to_float(pcg(hash_dimension(rng, dimension) ^ rng.index));
+}
+
+// Radical inverse with each digit permuted by a random affine map that
+// depends on the digits before it. Unlike a plain shift this spreads the
+// first samples of large bases over the whole interval.
+float scrambled_radical_inverse(uint base, uint index, uint seed) {
+    float inv_base = 1.0 / float(base);
+    float f = inv_base;
+    float r = 0.0;
+    // Until the digits no longer change the result
+    while (1.0 - float(base - 1u) * f < 1.0) {
+        uint digit = index % base;
+        index /= base;
+        uint a = 1u + seed % (base - 1u);
+        uint c = (seed >> 16u) % base;
+        r += f * float((a * digit + c) % base);
+        f *= inv_base;
+        seed = pcg(seed ^ digit);
+    }
+    return min(r, ONE_MINUS_EPSILON);
+}
+
+float halton_1d(Sampler rng, uint dimension) {
+    if (dimension >= HALTON_DIMENSIONS) {
+        return independent_1d(rng, dimension);
+    }
+    return scrambled_radical_inverse(HALTON_PRIMES[dimension], rng.index, hash_dimension(rng, dimension));
+}
+
+// Owen scrambling of the bits of `x` from the top, after Burley, "Practical
+// Hash-based Owen Scrambling"
+uint nested_uniform_scramble(uint x, uint seed) {
+    x = bitfieldReverse(x);
+    x += seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return bitfieldReverse(x);
+}
+
+// First two dimensions of the Sobol sequence
+uvec2 sobol_2d(uint index) {
+    uvec2 r = uvec2(0u);
+    uint v = 1u << 31u;
+    for (uint i = 0u; index != 0u; index >>= 1u, i++) {
+        if ((index & 1u) != 0u) {
+            r ^= uvec2(1u << (31u - i), v);
+        }
+        v ^= v >> 1u;
+    }
+    return r;
+}
+
+// Each dimension pair shuffles the sample order and scrambles the points on
+// its own, which keeps them stratified but uncorrelated with the others
+vec2 owen_sobol_2d(Sampler rng, uint dimension) {
+    uint seed = hash_dimension(rng, dimension);
+    uvec2 x = sobol_2d(nested_uniform_scramble(rng.index, seed));
+    x.x = nested_uniform_scramble(x.x, pcg(seed));
+    x.y = nested_uniform_scramble(x.y, pcg(seed + 1u));
+    return vec2(uint_to_float(x.x), uint_to_float(x.y));
+}
+
+float next_1d(inout Sampler rng) {
+    uint d = rng.dimension++;
+    switch (rng.kind) {
+    case SAMPLER_HALTON:
+        return halton_1d(rng, d);
+    case SAMPLER_SOBOL:
+        return owen_sobol_2d(rng, d).x;
+    default:
+        return independent_1d(rng, d);
+    }
+}
+
+vec2 next_2d(inout Sampler rng) {
+    uint d = rng.dimension;
+    rng.dimension += 2u;
+    switch (rng.kind) {
+    case SAMPLER_HALTON:
+        return vec2(halton_1d(rng, d), halton_1d(rng, d + 1u));
+    case SAMPLER_SOBOL:
+        return owen_sobol_2d(rng, d);
+    default:
+        return vec2(independent_1d(rng, d), independent_1d(rng, d + 1u));
+    }
+}
+
+// A choice then a 2D sample, e.g. of a lobe or light and a direction
+vec3 next_3d(inout Sampler rng) {
+    float x = next_1d(rng);
+    return vec3(x, next_2d(rng));
+}
+
+float next_tracking_1d(inout Sampler rng) {
+    return independent_1d(rng, TRACKING_DIMENSION + rng.tracking++);
+}
+
+vec2 next_tracking_2d(inout Sampler rng) {
+    float x = next_tracking_1d(rng);
+    return vec2(x, next_tracking_1d(rng));
+}
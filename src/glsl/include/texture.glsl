@@ -0,0 +1,193 @@
+// Kinds in the texture table, see texture.rs
+#define TEXTURE_IMAGE 0
+#define TEXTURE_IMAGE_SRGB 1
+#define TEXTURE_CHECKER 2
+#define TEXTURE_NOISE 3
+#define TEXTURE_MARBLE 4
+#define TEXTURE_GRADIENT 5
+#define TEXTURE_GRID 6
+
+float srgb_to_linear(float c) {
+    return c <= 0.04045 ? c / 12.92 : pow((c + 0.055) / 1.055, 2.4);
+}
+
+vec3 load_texel(uint offset, uint width, vec2 p, bool srgb) {
+    vec3 c = unpackUnorm4x8(textures.data[offset + uint(p.y) * width + uint(p.x)]).rgb;
+    if (srgb) {
+        c = vec3(srgb_to_linear(c.r), srgb_to_linear(c.g), srgb_to_linear(c.b));
+    }
+    return c;
+}
+
+// Bilinear filtering with repeat wrapping
+vec3 sample_image(uint offset, uint width, uint height, bool srgb, vec2 uv) {
+    vec2 size = vec2(width, height);
+    vec2 p = uv * size - 0.5;
+    vec2 f = fract(p);
+    vec2 p0 = mod(floor(p), size);
+    vec2 p1 = mod(p0 + 1.0, size);
+
+    vec3 c00 = load_texel(offset, width, p0, srgb);
+    vec3 c10 = load_texel(offset, width, vec2(p1.x, p0.y), srgb);
+    vec3 c01 = load_texel(offset, width, vec2(p0.x, p1.y), srgb);
+    vec3 c11 = load_texel(offset, width, p1, srgb);
+    return mix(mix(c00, c10, f.x), mix(c01, c11, f.x), f.y);
+}
+
+float load_param(uint offset, uint i) {
+    return uintBitsToFloat(textures.data[offset + i]);
+}
+
+vec3 load_param3(uint offset, uint i) {
+    return vec3(load_param(offset, i), load_param(offset, i + 1), load_param(offset, i + 2));
+}
+
+// Gradient noise with hashed lattice gradients, roughly in [-1, 1]
+float perlin(vec3 p) {
+    vec3 i = floor(p);
+    vec3 f = p - i;
+    vec3 w = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    float n = 0.0;
+    for (int c = 0; c < 8; c++) {
+        vec3 corner = vec3(c & 1, (c >> 1) & 1, (c >> 2) & 1);
+        vec3 g = 2.0 * hash3(uvec3(ivec3(i + corner))) - 1.0;
+        vec3 weight = mix(1.0 - w, w, corner);
+        n += weight.x * weight.y * weight.z * dot(g, f - corner);
+    }
+    return n;
+}
+
+float fbm(vec3 p, uint octaves) {
+    float sum = 0.0;
+    float amplitude = 0.5;
+    for (uint i = 0; i < octaves; i++) {
+        sum += amplitude * perlin(p);
+        p *= 2.0;
+        amplitude *= 0.5;
+    }
+    return sum;
+}
+
+float turbulence(vec3 p, uint octaves) {
+    float sum = 0.0;
+    float amplitude = 1.0;
+    for (uint i = 0; i < octaves; i++) {
+        sum += amplitude * abs(perlin(p));
+        p *= 2.0;
+        amplitude *= 0.5;
+    }
+    return sum;
+}
+
+// Density grids keep their depth, first slice in the grid atlas and largest
+// value in the table, they are only sampled by volumes
+float grid_majorant(uint index) {
+    return load_param(textures.data[4 + 4 * index + 1], 2);
+}
+
+// Voxel v of a grid, clamped to its edges so a lookup never reaches the
+// neighbouring grids in the atlas
+float load_voxel(ivec3 size, int z0, ivec3 v) {
+    v = clamp(v, ivec3(0), size - 1);
+    return texelFetch(sampler3D(grids, grid_sampler), ivec3(v.xy, v.z + z0), 0).r;
+}
+
+// Trilinear filtering between the voxel centers, clamped at the edges. uvw
+// spans the grid from 0 to 1. Done by hand since filtering R32Float textures
+// is an optional feature.
+float sample_grid(uint index, vec3 uvw) {
+    uint offset = textures.data[4 + 4 * index + 1];
+    ivec3 size = ivec3(textures.data[4 + 4 * index + 2], textures.data[4 + 4 * index + 3], textures.data[offset]);
+    int z0 = int(textures.data[offset + 1]);
+    vec3 p = clamp(uvw * vec3(size), vec3(0.5), vec3(size) - 0.5) - 0.5;
+    ivec3 v = ivec3(floor(p));
+    vec3 f = p - vec3(v);
+
+    float c00 = mix(load_voxel(size, z0, v), load_voxel(size, z0, v + ivec3(1, 0, 0)), f.x);
+    float c10 = mix(load_voxel(size, z0, v + ivec3(0, 1, 0)), load_voxel(size, z0, v + ivec3(1, 1, 0)), f.x);
+    float c01 = mix(load_voxel(size, z0, v + ivec3(0, 0, 1)), load_voxel(size, z0, v + ivec3(1, 0, 1)), f.x);
+    float c11 = mix(load_voxel(size, z0, v + ivec3(0, 1, 1)), load_voxel(size, z0, v + ivec3(1, 1, 1)), f.x);
+    return mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+}
+
+// Images are looked up by uv, procedural textures evaluated at p. White
+// without a texture.
+vec3 sample_texture(uint index, vec2 uv, vec3 p) {
+    if (index == NO_TEXTURE) {
+        return vec3(1.0);
+    }
+
+    uint kind = textures.data[4 + 4 * index + 0];
+    uint offset = textures.data[4 + 4 * index + 1];
+
+    if (kind == TEXTURE_IMAGE || kind == TEXTURE_IMAGE_SRGB) {
+        uint width = textures.data[4 + 4 * index + 2];
+        uint height = textures.data[4 + 4 * index + 3];
+        return sample_image(offset, width, height, kind == TEXTURE_IMAGE_SRGB, uv);
+    }
+
+    vec3 a = load_param3(offset, 0);
+    vec3 b = load_param3(offset, 3);
+    float scale = load_param(offset, 6);
+
+    float t;
+    if (kind == TEXTURE_CHECKER) {
+        vec3 cell = floor(p * scale + 0.5);
+        t = mod(cell.x + cell.y + cell.z, 2.0);
+    } else if (kind == TEXTURE_NOISE) {
+        t = 0.5 * (1.0 + fbm(p * scale, textures.data[offset + 7]));
+    } else if (kind == TEXTURE_MARBLE) {
+        uint octaves = textures.data[offset + 7];
+        t = 0.5 * (1.0 + sin(scale * p.z + 10.0 * turbulence(p * scale, octaves)));
+    } else {
+        vec3 start = load_param3(offset, 6);
+        vec3 d = load_param3(offset, 9) - start;
+        // Without a direction the gradient is `from` everywhere
+        t = dot(d, d) > 0.0 ? dot(p - start, d) / dot(d, d) : 0.0;
+    }
+    return mix(a, b, clamp(t, 0.0, 1.0));
+}
+
+// Step in texture space of the finite differences of bump maps
+#define BUMP_DELTA 1e-3
+
+// Shading normal after normal and bump mapping, in the frame of the texture
+// derivatives. Maps displace along the outward normal so bumps seen from the
+// back face are dents.
+vec3 perturb_normal(Material mat, HitRec rec) {
+    if (mat.normal_texture == NO_TEXTURE && mat.bump_texture == NO_TEXTURE) {
+        return rec.normal;
+    }
+
+    float side = rec.front_face ? 1.0 : -1.0;
+    vec3 n = side * rec.normal;
+
+    vec3 t = rec.dpdu - n * dot(n, rec.dpdu);
+    if (mat.normal_texture != NO_TEXTURE && dot(t, t) > 0.0) {
+        // Texture up is along decreasing v
+        t = normalize(t);
+        vec3 b = cross(n, t);
+        if (dot(b, rec.dpdv) > 0.0) {
+            b = -b;
+        }
+        vec3 m = 2.0 * sample_texture(mat.normal_texture, rec.uv, rec.point) - 1.0;
+        m.xy *= mat.normal_scale;
+        n = normalize(m.x * t + m.y * b + m.z * n);
+    }
+
+    if (mat.bump_texture != NO_TEXTURE) {
+        float h = sample_texture(mat.bump_texture, rec.uv, rec.point).g;
+        float hu = sample_texture(mat.bump_texture, rec.uv + vec2(BUMP_DELTA, 0.0), rec.point + BUMP_DELTA * rec.dpdu).g;
+        float hv = sample_texture(mat.bump_texture, rec.uv + vec2(0.0, BUMP_DELTA), rec.point + BUMP_DELTA * rec.dpdv).g;
+        vec3 dpdu = rec.dpdu + mat.bump_scale * (hu - h) / BUMP_DELTA * n;
+        vec3 dpdv = rec.dpdv + mat.bump_scale * (hv - h) / BUMP_DELTA * n;
+        vec3 bumped = cross(dpdu, dpdv);
+        if (dot(bumped, bumped) > 0.0) {
+            // Not sign(), which is zero for a bumped normal in the surface
+            n = normalize(bumped) * (dot(bumped, n) < 0.0 ? -1.0 : 1.0);
+        }
+    }
+
+    return side * n;
+}
@@ -9,8 +9,33 @@ struct Camera {
 
 struct Material {
     vec3 albedo;
-    uint type;
-    bool is_light;
+    float metallic;
+    vec3 emission;
+    float roughness;
+    vec3 absorption;
+    float ior;
+    uint albedo_texture;
+    uint roughness_texture;
+    uint emission_texture;
+    float specular;
+    float transmission;
+    float clearcoat;
+    float clearcoat_gloss;
+    float sheen;
+    float sheen_tint;
+    vec3 scattering;
+    float anisotropy;
+    uint normal_texture;
+    uint bump_texture;
+    float bump_scale;
+    float normal_scale;
+};
+
+// Homogeneous participating medium, coefficients per unit length
+struct Medium {
+    vec3 absorption;
+    vec3 scattering;
+    float anisotropy;
 };
 
 struct HitRec {
@@ -19,12 +44,47 @@ struct HitRec {
     float t;
     bool front_face;
     uint mat_ptr;
+    vec2 uv;
+    uint volume;
+    uint light;
+    // Derivatives of the point along the texture coordinates
+    vec3 dpdu;
+    vec3 dpdv;
 };
 
 struct Sphere {
     vec3 center;
     float radius;
     uint mat_ptr;
+    uint light;
+};
+
+struct Triangle {
+    vec3 v0;
+    vec3 v1;
+    vec3 v2;
+    vec3 n0;
+    vec3 n1;
+    vec3 n2;
+    vec2 uv0;
+    vec2 uv1;
+    vec2 uv2;
+    uint mat_ptr;
+};
+
+// Box filled with a heterogeneous medium, see geometry.rs
+struct Volume {
+    vec3 min;
+    uint mat_ptr;
+    vec3 max;
+    uint density;
+};
+
+// Emissive sphere, see geometry.rs
+struct Light {
+    vec3 center;
+    float radius;
+    uint mat_ptr;
 };
 
 struct BVHNode {
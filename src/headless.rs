@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cpu::Tracer;
+use crate::renderer::{RenderError, Renderer};
+use crate::scene::Scene;
+
+pub struct Options {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub output: PathBuf,
+}
+
+/// What a headless render ran on and how long tracing took.
+pub struct Report {
+    pub device: String,
+    pub elapsed: Duration,
+}
+
+/// Renders `options.samples` frames without a window and writes the averaged
+/// result to `options.output`.
+pub async fn render(scene: &Scene, options: &Options) -> Result<Report, RenderError> {
+    let mut renderer = Renderer::new(scene, options.width, options.height).await?;
+
+    let start = Instant::now();
+    renderer.accumulate(options.samples);
+    let pixels = renderer.read_back().await?;
+    let report = Report {
+        device: renderer.adapter_info().name.clone(),
+        elapsed: start.elapsed(),
+    };
+
+    write_image(&options.output, options.width, options.height, &pixels)?;
+    Ok(report)
+}
+
+/// Same as `render` but traced on the CPU.
+pub fn render_cpu(scene: &Scene, options: &Options) -> Result<Report, RenderError> {
+    let (width, height) = (options.width, options.height);
+    let tracer = Tracer::from_scene(scene, width, height);
+
+    let start = Instant::now();
+    let pixels = tracer.render(options.samples);
+    let report = Report {
+        device: String::from("the CPU"),
+        elapsed: start.elapsed(),
+    };
+
+    write_image(&options.output, width, height, &pixels)?;
+    Ok(report)
+}
+
+/// Writes linear radiance as an OpenEXR file, or sRGB encoded for PNG.
+pub fn write_image(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[[f32; 3]],
+) -> Result<(), RenderError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("exr") => {
+            exr::prelude::write_rgb_file(path, width as usize, height as usize, |x, y| {
+                let p = pixels[y * width as usize + x];
+                (p[0], p[1], p[2])
+            })
+            .map_err(RenderError::Exr)
+        }
+        Some("png") => {
+            let mut bytes = Vec::with_capacity(pixels.len() * 3);
+            for p in pixels {
+                bytes.extend(p.iter().map(|c| (linear_to_srgb(*c) * 255.0).round() as u8));
+            }
+            image::save_buffer(path, &bytes, width, height, image::ColorType::Rgb8)
+                .map_err(RenderError::Image)
+        }
+        _ => Err(RenderError::UnsupportedFormat(path.to_path_buf())),
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
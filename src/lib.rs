@@ -0,0 +1,34 @@
+//! Path tracer running in a wgpu compute shader, with a CPU reference
+//! implementation of the same integrator.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use wgpu_raytracer::{Renderer, Scene};
+//!
+//! let scene = Scene::load("scenes/default.ron")?;
+//! let mut renderer = Renderer::new(&scene, 640, 480).await?;
+//! renderer.accumulate(64);
+//! let pixels = renderer.read_back().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod aabb;
+pub mod bvh;
+pub mod cpu;
+pub mod environment;
+pub mod geometry;
+pub mod globals;
+pub mod headless;
+pub mod loaders;
+pub mod material;
+mod math;
+pub mod pipelines;
+mod renderer;
+pub mod scene;
+pub mod sky;
+pub mod texture;
+pub mod traits;
+
+pub use renderer::{RenderError, Renderer};
+pub use scene::{Camera, Integrator, Sampler, Scene, SceneError};
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use glam::{Mat4, Vec2, Vec3};
+
+use super::NO_MATERIAL;
+use crate::geometry::Triangle;
+use crate::material::Material;
+use crate::scene::Camera;
+use crate::texture::{Texture, NO_TEXTURE};
+
+pub use ::gltf::Error;
+
+#[derive(Debug)]
+pub struct Gltf {
+    /// Triangles in world space, material indices point into `materials`.
+    pub triangles: Vec<Triangle>,
+    /// Texture indices of the materials point into `textures`.
+    pub materials: Vec<Material>,
+    pub textures: Vec<Texture>,
+    /// First camera of the scene, in world space.
+    pub camera: Option<Camera>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Gltf, Error> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+
+    let mut textures = Textures {
+        images: &images,
+        textures: Vec::new(),
+        indices: HashMap::new(),
+    };
+    let materials = document
+        .materials()
+        .map(|m| convert_material(&m, &mut textures))
+        .collect();
+
+    let mut gltf = Gltf {
+        triangles: Vec::new(),
+        materials,
+        textures: textures.textures,
+        camera: None,
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            visit_node(&node, Mat4::identity(), &buffers, &mut gltf);
+        }
+    }
+
+    Ok(gltf)
+}
+
+/// Flattens the node hierarchy into world space triangles.
+fn visit_node(
+    node: &::gltf::Node,
+    parent: Mat4,
+    buffers: &[::gltf::buffer::Data],
+    gltf: &mut Gltf,
+) {
+    let world = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let mat_index = primitive
+                .material()
+                .index()
+                .map_or(NO_MATERIAL, |i| i as u32);
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(p) => p.map(Vec3::from).collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<Vec3>> =
+                reader.read_normals().map(|n| n.map(Vec3::from).collect());
+            let uvs: Option<Vec<Vec2>> = reader
+                .read_tex_coords(0)
+                .map(|t| t.into_f32().map(Vec2::from).collect());
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(i) => i.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            for [a, b, c] in triangulate(primitive.mode(), &indices) {
+                let (a, b, c) = (a as usize, b as usize, c as usize);
+                if a.max(b).max(c) >= positions.len() {
+                    continue;
+                }
+                let vertices = [positions[a], positions[b], positions[c]];
+                let mut tri = match &normals {
+                    Some(n) => Triangle::with_normals(vertices, [n[a], n[b], n[c]], mat_index),
+                    None => Triangle::new(vertices, mat_index),
+                };
+                if let Some(uv) = &uvs {
+                    tri = tri.with_uvs([uv[a], uv[b], uv[c]]);
+                }
+                gltf.triangles.push(tri.transform(&world));
+            }
+        }
+    }
+
+    if let (None, Some(camera)) = (&gltf.camera, node.camera()) {
+        if let ::gltf::camera::Projection::Perspective(p) = camera.projection() {
+            // glTF cameras look down their local -Z axis
+            let look_from = world.transform_point3(Vec3::zero());
+            let forward = world.transform_vector3(-Vec3::unit_z()).normalize();
+            gltf.camera = Some(Camera {
+                look_from,
+                look_at: look_from + forward,
+                vfov: p.yfov().to_degrees(),
+                aperture: 0.0,
+                focus_dist: 1.0,
+            });
+        }
+    }
+
+    for child in node.children() {
+        visit_node(&child, world, buffers, gltf);
+    }
+}
+
+fn triangulate(mode: ::gltf::mesh::Mode, indices: &[u32]) -> Vec<[u32; 3]> {
+    use ::gltf::mesh::Mode;
+
+    match mode {
+        Mode::Triangles => indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        Mode::TriangleStrip => (2..indices.len())
+            .map(|i| match i % 2 {
+                0 => [indices[i - 2], indices[i - 1], indices[i]],
+                _ => [indices[i - 1], indices[i - 2], indices[i]],
+            })
+            .collect(),
+        Mode::TriangleFan => (2..indices.len())
+            .map(|i| [indices[0], indices[i - 1], indices[i]])
+            .collect(),
+        // Points and lines have no surface
+        _ => Vec::new(),
+    }
+}
+
+/// Maps the metallic-roughness model onto the principled BSDF, blended
+/// materials become transmissive.
+fn convert_material(material: &::gltf::Material, textures: &mut Textures) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+
+    let mut converted = Material::new([r, g, b])
+        .with_metallic(pbr.metallic_factor())
+        .with_roughness(pbr.roughness_factor())
+        .with_emission(material.emissive_factor());
+    if material.alpha_mode() == ::gltf::material::AlphaMode::Blend && a < 1.0 {
+        converted = converted.with_transmission(1.0 - a);
+    }
+    if let Some(normal) = material.normal_texture() {
+        converted = converted.with_normal_scale(normal.scale());
+    }
+
+    // Only the first set of texture coordinates is imported
+    Material {
+        albedo_texture: textures.get(pbr.base_color_texture().map(|i| i.texture()), true),
+        roughness_texture: textures
+            .get(pbr.metallic_roughness_texture().map(|i| i.texture()), false),
+        emission_texture: textures.get(material.emissive_texture().map(|i| i.texture()), true),
+        normal_texture: textures.get(material.normal_texture().map(|n| n.texture()), false),
+        ..converted
+    }
+}
+
+/// Converts each image once per color space, on first use.
+struct Textures<'a> {
+    images: &'a [::gltf::image::Data],
+    textures: Vec<Texture>,
+    indices: HashMap<(usize, bool), u32>,
+}
+
+impl Textures<'_> {
+    fn get(&mut self, texture: Option<::gltf::Texture>, srgb: bool) -> u32 {
+        let source = match texture {
+            Some(t) => t.source().index(),
+            None => return NO_TEXTURE,
+        };
+
+        let (images, textures) = (self.images, &mut self.textures);
+        *self.indices.entry((source, srgb)).or_insert_with(|| {
+            textures.push(to_texture(&images[source], srgb));
+            textures.len() as u32 - 1
+        })
+    }
+}
+
+fn to_texture(image: &::gltf::image::Data, srgb: bool) -> Texture {
+    use ::gltf::image::Format;
+
+    let (channels, bytes, bgr) = match image.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::B8G8R8 => (3, 1, true),
+        Format::B8G8R8A8 => (4, 1, true),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+    };
+    // Keep the most significant byte of 16 bit channels
+    let read = |px: &[u8], c: usize| -> u8 { px[bytes * c + bytes - 1] };
+
+    let mut rgba = Vec::with_capacity(4 * (image.width * image.height) as usize);
+    for px in image.pixels.chunks_exact(channels * bytes) {
+        let texel = match channels {
+            1 => [read(px, 0), read(px, 0), read(px, 0), 255],
+            2 => [read(px, 0), read(px, 0), read(px, 0), read(px, 1)],
+            3 if bgr => [read(px, 2), read(px, 1), read(px, 0), 255],
+            3 => [read(px, 0), read(px, 1), read(px, 2), 255],
+            _ if bgr => [read(px, 2), read(px, 1), read(px, 0), read(px, 3)],
+            _ => [read(px, 0), read(px, 1), read(px, 2), read(px, 3)],
+        };
+        rgba.extend_from_slice(&texel);
+    }
+
+    Texture::from_rgba8(image.width, image.height, &rgba, srgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let gltf = load("scenes/meshes/quad.gltf").unwrap();
+
+        assert_eq!(gltf.materials.len(), 2);
+        assert_eq!(gltf.materials[0].metallic, 1.0);
+        assert_eq!(gltf.materials[0].roughness, 0.2);
+        assert!(gltf.materials[1].is_light());
+
+        // The quad node is translated by its parent
+        assert_eq!(gltf.triangles.len(), 2);
+        for t in gltf.triangles.iter() {
+            for v in t.vertices.iter() {
+                assert_eq!(v.y(), 2.0);
+            }
+            assert_eq!(t.normals[0], Vec3::unit_y());
+        }
+
+        let camera = gltf.camera.unwrap();
+        assert_eq!(camera.look_from, Vec3::new(0.0, 2.0, 5.0));
+        assert_eq!(camera.look_at, Vec3::new(0.0, 2.0, 4.0));
+        assert!((camera.vfov - 45.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_triangulate() {
+        use ::gltf::mesh::Mode;
+
+        let indices = [0, 1, 2, 3];
+        assert_eq!(
+            triangulate(Mode::TriangleStrip, &indices),
+            vec![[0, 1, 2], [2, 1, 3]]
+        );
+        assert_eq!(
+            triangulate(Mode::TriangleFan, &indices),
+            vec![[0, 1, 2], [0, 2, 3]]
+        );
+        assert!(triangulate(Mode::Lines, &indices).is_empty());
+    }
+
+    #[test]
+    fn test_to_texture() {
+        let image = ::gltf::image::Data {
+            pixels: vec![10, 20, 30, 40, 50, 60],
+            format: ::gltf::image::Format::B8G8R8,
+            width: 2,
+            height: 1,
+        };
+        let texture = match to_texture(&image, true) {
+            Texture::Image(i) => i,
+            t => panic!("{:?}", t),
+        };
+
+        assert!(texture.srgb);
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(
+            texture.texels,
+            vec![
+                u32::from_le_bytes([30, 20, 10, 255]),
+                u32::from_le_bytes([60, 50, 40, 255])
+            ]
+        );
+    }
+}
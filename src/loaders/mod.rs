@@ -0,0 +1,6 @@
+pub mod gltf;
+pub mod obj;
+pub mod vol;
+
+/// Material index of imported faces that don't reference a material.
+pub const NO_MATERIAL: u32 = 0xFFFFFFFF;
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Vec2, Vec3};
+
+use super::NO_MATERIAL;
+use crate::geometry::Triangle;
+use crate::material::Material;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Malformed {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Malformed {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// The subset of an MTL material the renderer understands.
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emission: [f32; 3],
+    pub ior: f32,
+    pub dissolve: f32,
+    /// Phong specular exponent `Ns`
+    pub shininess: Option<f32>,
+    /// `map_Kd`, `map_Pr` and `map_Ke` image paths.
+    pub diffuse_map: Option<PathBuf>,
+    pub roughness_map: Option<PathBuf>,
+    pub emission_map: Option<PathBuf>,
+    /// `norm` tangent space normal map and `bump` height map paths.
+    pub normal_map: Option<PathBuf>,
+    pub bump_map: Option<PathBuf>,
+    /// `-bm` multiplier of the bump map.
+    pub bump_scale: f32,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: [0.8; 3],
+            specular: [0.0; 3],
+            emission: [0.0; 3],
+            ior: 1.0,
+            dissolve: 1.0,
+            shininess: None,
+            diffuse_map: None,
+            roughness_map: None,
+            emission_map: None,
+            normal_map: None,
+            bump_map: None,
+            bump_scale: 1.0,
+        }
+    }
+
+    /// Transparent materials become glass and materials with a stronger
+    /// specular than diffuse color metals, the others are matte without `Ks`.
+    /// The roughness is that of the Beckmann distribution equivalent to `Ns`.
+    /// `Ke` is emitted on top. Texture maps are left to the caller.
+    pub fn to_material(&self) -> Material {
+        let max = |c: [f32; 3]| c[0].max(c[1]).max(c[2]);
+        let roughness = self
+            .shininess
+            .map(|ns| (2.0 / (ns.max(0.0) + 2.0)).sqrt().sqrt());
+
+        let material = if self.dissolve < 1.0 {
+            Material::glass(self.ior)
+        } else if max(self.specular) > max(self.diffuse) {
+            Material::metal(self.specular).with_roughness(roughness.unwrap_or(0.0))
+        } else if max(self.specular) > 0.0 {
+            let material = Material::new(self.diffuse);
+            material.with_roughness(roughness.unwrap_or(material.roughness))
+        } else {
+            Material::new(self.diffuse).with_specular(0.0)
+        };
+        material
+            .with_emission(self.emission)
+            .with_bump_scale(self.bump_scale)
+    }
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub name: String,
+    /// Material indices point into `Obj::materials`, or are `NO_MATERIAL`.
+    pub triangles: Vec<Triangle>,
+}
+
+#[derive(Debug)]
+pub struct Obj {
+    pub objects: Vec<Object>,
+    pub materials: Vec<MtlMaterial>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Obj, ObjError> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    parse(&src, path, |lib| {
+        let mtl_path = dir.join(lib);
+        let src = fs::read_to_string(&mtl_path).map_err(|e| ObjError::Io(mtl_path.clone(), e))?;
+        parse_mtl(&src, &mtl_path)
+    })
+}
+
+fn parse<F>(src: &str, path: &Path, mut load_mtl: F) -> Result<Obj, ObjError>
+where
+    F: FnMut(&str) -> Result<Vec<MtlMaterial>, ObjError>,
+{
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut tex_coords: Vec<Vec2> = Vec::new();
+
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    let mut material_names: HashMap<String, u32> = HashMap::new();
+    let mut current_material = NO_MATERIAL;
+
+    let mut objects = vec![Object {
+        name: String::new(),
+        triangles: Vec::new(),
+    }];
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |message: String| ObjError::Malformed {
+            path: path.to_path_buf(),
+            line: line_no,
+            message,
+        };
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args, 3..=4).map_err(err)?),
+            "vn" => normals.push(parse_vec3(&args, 3..=3).map_err(err)?.normalize()),
+            "vt" => {
+                let f = parse_floats(&args, 1..=3).map_err(err)?;
+                // OBJ puts the origin at the bottom left
+                tex_coords.push(Vec2::new(f[0], 1.0 - f.get(1).unwrap_or(&0.0)));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err(format!(
+                        "face needs at least 3 vertices, found {}",
+                        args.len()
+                    )));
+                }
+
+                let mut face = Vec::with_capacity(args.len());
+                for a in args.iter() {
+                    face.push(
+                        parse_face_vertex(a, positions.len(), tex_coords.len(), normals.len())
+                            .map_err(err)?,
+                    );
+                }
+
+                // Fan triangulation
+                let object = objects.last_mut().unwrap();
+                for j in 1..face.len() - 1 {
+                    let (a, b, c) = (face[0], face[j], face[j + 1]);
+                    let vertices = [positions[a.0], positions[b.0], positions[c.0]];
+                    let mut tri = match (a.2, b.2, c.2) {
+                        (Some(na), Some(nb), Some(nc)) => Triangle::with_normals(
+                            vertices,
+                            [normals[na], normals[nb], normals[nc]],
+                            current_material,
+                        ),
+                        _ => Triangle::new(vertices, current_material),
+                    };
+                    if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+                        tri = tri.with_uvs([tex_coords[ta], tex_coords[tb], tex_coords[tc]]);
+                    }
+                    object.triangles.push(tri);
+                }
+            }
+            "o" | "g" => {
+                let name = args.join(" ");
+                let object = objects.last_mut().unwrap();
+                if object.triangles.is_empty() {
+                    object.name = name;
+                } else {
+                    objects.push(Object {
+                        name,
+                        triangles: Vec::new(),
+                    });
+                }
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(err("mtllib needs a file name".to_string()));
+                }
+                for m in load_mtl(&args.join(" "))? {
+                    material_names.insert(m.name.clone(), materials.len() as u32);
+                    materials.push(m);
+                }
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = match material_names.get(&name) {
+                    Some(index) => *index,
+                    None => return Err(err(format!("unknown material \"{}\"", name))),
+                };
+            }
+            // Smoothing groups, lines, points, ...
+            _ => {}
+        }
+    }
+
+    objects.retain(|o| !o.triangles.is_empty());
+
+    Ok(Obj { objects, materials })
+}
+
+pub fn parse_mtl(src: &str, path: &Path) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |message: String| ObjError::Malformed {
+            path: path.to_path_buf(),
+            line: line_no,
+            message,
+        };
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(err("newmtl needs a name".to_string()));
+            }
+            materials.push(MtlMaterial::new(&args.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(m) => m,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_color(&args).map_err(err)?,
+            "Ks" => material.specular = parse_color(&args).map_err(err)?,
+            "Ke" => material.emission = parse_color(&args).map_err(err)?,
+            "Ns" => material.shininess = Some(parse_floats(&args, 1..=1).map_err(err)?[0]),
+            "Ni" => material.ior = parse_floats(&args, 1..=1).map_err(err)?[0],
+            "d" => material.dissolve = parse_floats(&args, 1..=1).map_err(err)?[0],
+            "Tr" => material.dissolve = 1.0 - parse_floats(&args, 1..=1).map_err(err)?[0],
+            "map_Kd" => material.diffuse_map = Some(parse_map(&args, path).map_err(err)?),
+            "map_Pr" => material.roughness_map = Some(parse_map(&args, path).map_err(err)?),
+            "map_Ke" => material.emission_map = Some(parse_map(&args, path).map_err(err)?),
+            "norm" => material.normal_map = Some(parse_map(&args, path).map_err(err)?),
+            "bump" | "map_Bump" | "map_bump" => {
+                material.bump_map = Some(parse_map(&args, path).map_err(err)?);
+                if let Some(i) = args.iter().position(|a| *a == "-bm") {
+                    let bm = args.get(i + 1..i + 2).unwrap_or_default();
+                    material.bump_scale = parse_floats(bm, 1..=1).map_err(err)?[0];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+fn parse_floats(args: &[&str], count: std::ops::RangeInclusive<usize>) -> Result<Vec<f32>, String> {
+    if !count.contains(&args.len()) {
+        return Err(format!(
+            "expected {} to {} numbers, found {}",
+            count.start(),
+            count.end(),
+            args.len()
+        ));
+    }
+
+    args.iter()
+        .map(|a| {
+            a.parse::<f32>()
+                .map_err(|_| format!("\"{}\" is not a number", a))
+        })
+        .collect()
+}
+
+fn parse_vec3(args: &[&str], count: std::ops::RangeInclusive<usize>) -> Result<Vec3, String> {
+    let f = parse_floats(args, count)?;
+    Ok(Vec3::new(f[0], f[1], f[2]))
+}
+
+/// `Kd r g b` or `Kd r` for a grey.
+fn parse_color(args: &[&str]) -> Result<[f32; 3], String> {
+    let f = parse_floats(args, 1..=3)?;
+    match f.len() {
+        1 => Ok([f[0]; 3]),
+        3 => Ok([f[0], f[1], f[2]]),
+        n => Err(format!("expected 1 or 3 color components, found {}", n)),
+    }
+}
+
+/// `map_Kd [options] file`, options are ignored. The path is relative to the
+/// MTL file.
+fn parse_map(args: &[&str], mtl_path: &Path) -> Result<PathBuf, String> {
+    match args.last() {
+        Some(file) => Ok(mtl_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(file)),
+        None => Err("texture map needs a file name".to_string()),
+    }
+}
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn` into 0 based position, texture
+/// coordinate and normal indices.
+fn parse_face_vertex(
+    s: &str,
+    num_positions: usize,
+    num_tex_coords: usize,
+    num_normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = s.split('/');
+
+    let v = resolve_index(parts.next().unwrap_or(""), num_positions, "vertex")?;
+    let vt = match parts.next() {
+        Some("") | None => None,
+        Some(vt) => Some(resolve_index(vt, num_tex_coords, "texture coordinate")?),
+    };
+    let vn = match parts.next() {
+        Some("") | None => None,
+        Some(vn) => Some(resolve_index(vn, num_normals, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex \"{}\"", s));
+    }
+
+    Ok((v, vt, vn))
+}
+
+/// OBJ indices are 1 based, negative indices are relative to the end.
+fn resolve_index(s: &str, len: usize, what: &str) -> Result<usize, String> {
+    let i = s
+        .parse::<i64>()
+        .map_err(|_| format!("\"{}\" is not a valid {} index", s, what))?;
+
+    let index = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || index < 0 || index >= len as i64 {
+        return Err(format!(
+            "{} index {} out of range, {} defined so far",
+            what, i, len
+        ));
+    }
+
+    Ok(index as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTL: &str = "
+        newmtl red
+        Kd 0.8 0.1 0.1
+        map_Kd -s 2 2 1 textures/red.png
+        map_Bump -bm 0.05 textures/red_height.png
+        norm textures/red_normal.png
+        newmtl lamp
+        Ke 10 10 10
+        newmtl glass
+        Kd 0 0 0
+        Ni 1.45
+        d 0.1
+        newmtl gold
+        Kd 0.1 0.1 0.1
+        Ks 1.0 0.8 0.3
+        Ns 98
+    ";
+
+    fn parse_str(src: &str) -> Result<Obj, ObjError> {
+        parse(src, Path::new("test.obj"), |_| {
+            parse_mtl(MTL, Path::new("test.mtl"))
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let obj = parse_str(
+            "
+            mtllib test.mtl
+            o quad
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vn 0 0 1
+            vt 0 0
+            usemtl red
+            f 1//1 2//1 3//1 4//1
+            o tri
+            usemtl lamp
+            f -4/1 -3/1 -2/1
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(obj.objects.len(), 2);
+        assert_eq!(obj.objects[0].name, "quad");
+        assert_eq!(obj.objects[0].triangles.len(), 2);
+        assert_eq!(obj.objects[0].triangles[0].mat_index, 0);
+        assert_eq!(obj.objects[0].triangles[0].normals[0], Vec3::unit_z());
+        assert_eq!(obj.objects[1].triangles.len(), 1);
+        assert_eq!(obj.objects[1].triangles[0].mat_index, 1);
+        assert_eq!(obj.objects[1].triangles[0].vertices[0], Vec3::zero());
+        assert_eq!(obj.objects[1].triangles[0].uvs[0], Vec2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn test_materials() {
+        let m = parse_mtl(MTL, Path::new("test.mtl")).unwrap();
+        assert_eq!(m.len(), 4);
+
+        let red = m[0].to_material();
+        assert_eq!(
+            (red.albedo, red.metallic, red.is_light()),
+            ([0.8, 0.1, 0.1], 0.0, false)
+        );
+        assert_eq!(red.specular, 0.0);
+        assert_eq!(m[0].diffuse_map, Some(PathBuf::from("textures/red.png")));
+        assert_eq!(
+            m[0].bump_map,
+            Some(PathBuf::from("textures/red_height.png"))
+        );
+        assert_eq!(red.bump_scale, 0.05);
+        // -bm only scales the height map
+        assert_eq!(
+            m[0].normal_map,
+            Some(PathBuf::from("textures/red_normal.png"))
+        );
+        assert_eq!(red.normal_scale, 1.0);
+        assert_eq!(m[1].to_material().bump_scale, 1.0);
+        assert!(m[1].to_material().is_light());
+        assert_eq!(m[2].ior, 1.45);
+        assert_eq!(m[2].to_material().ior, 1.45);
+        assert_eq!(m[2].to_material().transmission, 1.0);
+        assert_eq!(m[3].to_material().metallic, 1.0);
+        assert!((m[3].to_material().roughness - 0.376).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let cases = [
+            ("v 0 0\n", 1),
+            ("v 0 0 0\nv 1 0 0\nf 1 2\n", 3),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", 4),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1//1 2//1 3//1\n", 4),
+            ("mtllib test.mtl\n\nusemtl blue\n", 3),
+            ("v 0 zero 0\n", 1),
+        ];
+
+        for (src, line) in cases.iter() {
+            match parse_str(src) {
+                Err(ObjError::Malformed { line: l, .. }) => assert_eq!(l, *line, "{}", src),
+                r => panic!("expected error for {:?}, got {:?}", src, r),
+            }
+        }
+    }
+}
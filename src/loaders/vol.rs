@@ -0,0 +1,161 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+
+use crate::texture::Grid;
+
+// Mitsuba's binary grid format, all values little endian:
+//   "VOL", version u8 (3), encoding i32, x, y and z resolution i32,
+//   channels i32, bounding box min and max [f32; 3]
+//   then the values with the channels varying fastest, then x, y and z.
+const HEADER_SIZE: usize = 48;
+const ENCODING_F32: i32 = 1;
+const ENCODING_U8: i32 = 3;
+
+#[derive(Debug)]
+pub enum VolError {
+    Io(PathBuf, std::io::Error),
+    Malformed(PathBuf, String),
+}
+
+impl fmt::Display for VolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VolError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            VolError::Malformed(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for VolError {}
+
+#[derive(Debug)]
+pub struct Vol {
+    /// Channels are averaged into a single density.
+    pub grid: Grid,
+    /// Bounding box stored in the file.
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vol, VolError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| VolError::Io(path.to_path_buf(), e))?;
+    parse(&bytes).map_err(|message| VolError::Malformed(path.to_path_buf(), message))
+}
+
+fn parse(bytes: &[u8]) -> Result<Vol, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..3] != b"VOL" {
+        return Err("not a volume file".to_string());
+    }
+    if bytes[3] != 3 {
+        return Err(format!("unsupported version {}", bytes[3]));
+    }
+
+    let word = |i: usize| {
+        [
+            bytes[4 * i],
+            bytes[4 * i + 1],
+            bytes[4 * i + 2],
+            bytes[4 * i + 3],
+        ]
+    };
+    let int = |i: usize| i32::from_le_bytes(word(i));
+    let float = |i: usize| f32::from_le_bytes(word(i));
+
+    let encoding = int(1);
+    let (width, height, depth, channels) = (int(2), int(3), int(4), int(5));
+    if width <= 0 || height <= 0 || depth <= 0 || channels <= 0 {
+        return Err("empty grid".to_string());
+    }
+    let min = Vec3::new(float(6), float(7), float(8));
+    let max = Vec3::new(float(9), float(10), float(11));
+
+    let value_size = match encoding {
+        ENCODING_F32 => 4,
+        ENCODING_U8 => 1,
+        _ => return Err(format!("unsupported encoding {}", encoding)),
+    };
+    let channels = channels as usize;
+    let expected = [width, height, depth]
+        .iter()
+        .try_fold(channels * value_size, |n, &d| n.checked_mul(d as usize))
+        .ok_or_else(|| "grid size overflows".to_string())?;
+    let data = &bytes[HEADER_SIZE..];
+    if data.len() != expected {
+        return Err(format!(
+            "expected {} bytes of data, found {}",
+            expected,
+            data.len()
+        ));
+    }
+
+    let values = data
+        .chunks_exact(channels * value_size)
+        .map(|voxel| {
+            let sum: f32 = match encoding {
+                ENCODING_F32 => voxel
+                    .chunks_exact(4)
+                    .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                    .sum(),
+                _ => voxel.iter().map(|v| *v as f32 / 255.0).sum(),
+            };
+            sum / channels as f32
+        })
+        .collect();
+
+    Ok(Vol {
+        grid: Grid {
+            width: width as u32,
+            height: height as u32,
+            depth: depth as u32,
+            values,
+        },
+        min,
+        max,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(encoding: i32, size: [i32; 3], channels: i32) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for i in [encoding, size[0], size[1], size[2], channels].iter() {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        for f in [-1.0f32, 0.0, -1.0, 1.0, 2.0, 1.0].iter() {
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut bytes = header(ENCODING_F32, [2, 1, 1], 2);
+        for f in [0.5f32, 1.5, 3.0, 1.0].iter() {
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        let vol = parse(&bytes).unwrap();
+        assert_eq!((vol.grid.width, vol.grid.height, vol.grid.depth), (2, 1, 1));
+        assert_eq!(vol.grid.values, vec![1.0, 2.0]);
+        assert_eq!(vol.min, Vec3::new(-1.0, 0.0, -1.0));
+        assert_eq!(vol.max, Vec3::new(1.0, 2.0, 1.0));
+
+        let mut bytes = header(ENCODING_U8, [1, 1, 2], 1);
+        bytes.extend_from_slice(&[0, 255]);
+        assert_eq!(parse(&bytes).unwrap().grid.values, vec![0.0, 1.0]);
+
+        // Truncated data and other encodings are rejected
+        let mut bytes = header(ENCODING_F32, [2, 2, 2], 1);
+        bytes.extend_from_slice(&[0; 4]);
+        assert!(parse(&bytes).is_err());
+        assert!(parse(&header(2, [1, 1, 1], 1)).is_err());
+        // So are sizes whose data length overflows
+        let huge = header(ENCODING_F32, [i32::MAX, i32::MAX, i32::MAX], i32::MAX);
+        assert!(parse(&huge).is_err());
+    }
+}
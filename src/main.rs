@@ -1,31 +1,128 @@
-mod aabb;
 mod app;
-mod bvh;
-mod geometry;
-mod globals;
-mod material;
-mod math;
-mod pipelines;
-mod traits;
 
 use futures::executor::block_on;
+use wgpu_raytracer::{headless, scene, RenderError};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
+const DEFAULT_SCENE: &str = "scenes/default.ron";
+
+const USAGE: &str = "usage:
+    wgpu-raytracer [SCENE]
+    wgpu-raytracer render [SCENE] [--output FILE] [--width N] [--height N] [--samples N] [--max-depth N] [--sampler independent|halton|sobol] [--cpu]";
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|a| a.as_str()) {
+        Some("render") => render(&args[1..]),
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        _ => view(args.first().map_or(DEFAULT_SCENE, |a| a.as_str())),
+    }
+}
+
+fn load_scene(path: &str) -> scene::Scene {
+    match scene::Scene::load(path) {
+        Ok(scene) => scene,
+        Err(e) => exit_with_error(&format!("{}: {}", path, e)),
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Offline render straight to an image file.
+fn render(args: &[String]) {
+    let mut scene_path = DEFAULT_SCENE;
+    let mut options = headless::Options {
+        width: 1280,
+        height: 720,
+        samples: 128,
+        output: "render.png".into(),
+    };
+
+    let mut cpu = false;
+    let mut max_depth = None;
+    let mut sampler = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next() {
+            Some(v) => v.clone(),
+            None => exit_with_error(&format!("{} needs a value\n{}", name, USAGE)),
+        };
+        let number = |name: &str, v: String| match v.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => exit_with_error(&format!("{} must be a positive integer, got {}", name, v)),
+        };
+
+        match arg.as_str() {
+            "--output" | "-o" => options.output = value(arg).into(),
+            "--width" => options.width = number(arg, value(arg)),
+            "--height" => options.height = number(arg, value(arg)),
+            "--samples" | "-s" => options.samples = number(arg, value(arg)),
+            "--max-depth" => max_depth = Some(number(arg, value(arg))),
+            "--sampler" => {
+                sampler = match value(arg).as_str() {
+                    "independent" => Some(scene::Sampler::Independent),
+                    "halton" => Some(scene::Sampler::Halton),
+                    "sobol" => Some(scene::Sampler::Sobol),
+                    v => exit_with_error(&format!("unknown sampler {}\n{}", v, USAGE)),
+                }
+            }
+            "--cpu" => cpu = true,
+            a if !a.starts_with('-') => scene_path = a,
+            a => exit_with_error(&format!("unknown option {}\n{}", a, USAGE)),
+        }
+    }
+
+    let mut scene = load_scene(scene_path);
+    if let Some(max_depth) = max_depth {
+        scene.integrator.max_depth = max_depth;
+    }
+    if let Some(sampler) = sampler {
+        scene.integrator.sampler = sampler;
+    }
+    let result = match cpu {
+        true => headless::render_cpu(&scene, &options),
+        false => match block_on(headless::render(&scene, &options)) {
+            Err(RenderError::NoAdapter) => {
+                eprintln!("No GPU adapter found, falling back to the CPU tracer");
+                headless::render_cpu(&scene, &options)
+            }
+            result => result,
+        },
+    };
+    match result {
+        Ok(report) => println!(
+            "{} samples in {:.2}s on {}",
+            options.samples,
+            report.elapsed.as_secs_f32(),
+            report.device
+        ),
+        Err(e) => exit_with_error(&e.to_string()),
+    }
+    println!("Wrote {}", options.output.display());
+}
+
+fn view(scene_path: &str) {
+    let scene = load_scene(scene_path);
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    let mut state = block_on(app::State::new(&window));
+    let mut state = match block_on(app::State::new(&window, &scene)) {
+        Ok(state) => state,
+        Err(e) => exit_with_error(&e.to_string()),
+    };
 
     event_loop.run(move |event, _, control_flow| match event {
-        Event::RedrawRequested(_) => {
-            state.update();
-            state.render();
-        }
+        Event::RedrawRequested(_) => state.render(),
 
         Event::MainEventsCleared => window.request_redraw(),
 
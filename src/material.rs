@@ -1,23 +1,197 @@
+use crate::texture::NO_TEXTURE;
 use crate::traits::AsBytes;
 
+/// Principled BSDF parameters, see bsdf.glsl.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Material {
+    /// Base color, the diffuse albedo or the reflectance of metals.
     pub albedo: [f32; 3],
-    pub type_flag: u32,
-    pub is_light: bool,
+    /// Blend from a dielectric to a metal, usually 0 or 1.
+    pub metallic: f32,
+    /// Emitted radiance, added on top of whatever the surface reflects.
+    pub emission: [f32; 3],
+    /// Perceptual roughness of the specular lobes, 0 is a mirror.
+    pub roughness: f32,
+    /// Beer-Lambert absorption coefficients per unit length inside
+    /// transmissive materials, 0 is clear.
+    pub absorption: [f32; 3],
+    /// Index of refraction of transmissive materials.
+    pub ior: f32,
+    /// Texture multiplying `albedo`, or `NO_TEXTURE`.
+    pub albedo_texture: u32,
+    /// Texture whose green channel multiplies `roughness`, or `NO_TEXTURE`.
+    pub roughness_texture: u32,
+    /// Texture multiplying `emission`, or `NO_TEXTURE`.
+    pub emission_texture: u32,
+    /// Reflectance of opaque dielectrics, 0.5 is 4% at normal incidence.
+    pub specular: f32,
+    /// Blend from an opaque dielectric to a refractive one.
+    pub transmission: f32,
+    /// Strength of a white specular coat on top.
+    pub clearcoat: f32,
+    /// 0 is a satin coat, 1 a glossy one.
+    pub clearcoat_gloss: f32,
+    /// Grazing retroreflection of cloth.
+    pub sheen: f32,
+    /// Blend of the sheen color from white to the hue of `albedo`.
+    pub sheen_tint: f32,
+    pub pad: [u32; 3],
+    /// Scattering coefficients per unit length inside transmissive
+    /// materials, for smoke or fog bounded by an index matched surface.
+    pub scattering: [f32; 3],
+    /// Henyey-Greenstein asymmetry of the scattering, from -1 (backward) to
+    /// 1 (forward).
+    pub anisotropy: f32,
+    /// Tangent space normal map with +Y up in the image, or `NO_TEXTURE`.
+    pub normal_texture: u32,
+    /// Height map whose green channel displaces the normal, or `NO_TEXTURE`.
+    pub bump_texture: u32,
+    /// Heights of bump maps in world units.
+    pub bump_scale: f32,
+    /// Strength of normal maps, scaling their tangent components.
+    pub normal_scale: f32,
 }
 unsafe impl bytemuck::Pod for Material {}
 unsafe impl bytemuck::Zeroable for Material {}
 
 impl Material {
-    pub fn new(albedo: [f32; 3], type_flag: u32, is_light: bool) -> Self {
+    /// A rough dielectric.
+    pub fn new(albedo: [f32; 3]) -> Self {
         Material {
             albedo,
-            type_flag,
-            is_light,
+            metallic: 0.0,
+            emission: [0.0; 3],
+            roughness: 0.5,
+            absorption: [0.0; 3],
+            ior: 1.5,
+            albedo_texture: NO_TEXTURE,
+            roughness_texture: NO_TEXTURE,
+            emission_texture: NO_TEXTURE,
+            specular: 0.5,
+            transmission: 0.0,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            pad: [0; 3],
+            scattering: [0.0; 3],
+            anisotropy: 0.0,
+            normal_texture: NO_TEXTURE,
+            bump_texture: NO_TEXTURE,
+            bump_scale: 1.0,
+            normal_scale: 1.0,
         }
     }
+
+    /// A polished metal reflecting `albedo`.
+    pub fn metal(albedo: [f32; 3]) -> Self {
+        Material::new(albedo).with_metallic(1.0).with_roughness(0.0)
+    }
+
+    /// Clear glass.
+    pub fn glass(ior: f32) -> Self {
+        Material::new([1.0; 3])
+            .with_transmission(1.0)
+            .with_roughness(0.0)
+            .with_ior(ior)
+    }
+
+    pub fn with_emission(self, emission: [f32; 3]) -> Self {
+        Material { emission, ..self }
+    }
+
+    pub fn with_metallic(self, metallic: f32) -> Self {
+        Material {
+            metallic: metallic.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_roughness(self, roughness: f32) -> Self {
+        Material {
+            roughness: roughness.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_specular(self, specular: f32) -> Self {
+        Material {
+            specular: specular.max(0.0),
+            ..self
+        }
+    }
+
+    pub fn with_transmission(self, transmission: f32) -> Self {
+        Material {
+            transmission: transmission.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_clearcoat(self, clearcoat: f32, gloss: f32) -> Self {
+        Material {
+            clearcoat: clearcoat.max(0.0),
+            clearcoat_gloss: gloss.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_sheen(self, sheen: f32, tint: f32) -> Self {
+        Material {
+            sheen: sheen.max(0.0),
+            sheen_tint: tint.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn with_ior(self, ior: f32) -> Self {
+        Material { ior, ..self }
+    }
+
+    pub fn with_absorption(self, absorption: [f32; 3]) -> Self {
+        Material { absorption, ..self }
+    }
+
+    pub fn with_scattering(self, scattering: [f32; 3], anisotropy: f32) -> Self {
+        Material {
+            scattering,
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+            ..self
+        }
+    }
+
+    pub fn with_bump_scale(self, bump_scale: f32) -> Self {
+        Material { bump_scale, ..self }
+    }
+
+    pub fn with_normal_scale(self, normal_scale: f32) -> Self {
+        Material {
+            normal_scale,
+            ..self
+        }
+    }
+
+    /// Shifts the texture indices, for materials whose textures are appended
+    /// after `offset` others.
+    pub fn with_texture_offset(self, offset: u32) -> Self {
+        let shift = |t: u32| match t {
+            NO_TEXTURE => t,
+            _ => t + offset,
+        };
+        Material {
+            albedo_texture: shift(self.albedo_texture),
+            roughness_texture: shift(self.roughness_texture),
+            emission_texture: shift(self.emission_texture),
+            normal_texture: shift(self.normal_texture),
+            bump_texture: shift(self.bump_texture),
+            ..self
+        }
+    }
+
+    pub fn is_light(&self) -> bool {
+        self.emission.iter().any(|e| *e > 0.0)
+    }
 }
 
 impl AsBytes for Vec<Material> {
@@ -25,17 +199,73 @@ impl AsBytes for Vec<Material> {
         let mut flat: Vec<u8> = vec![];
         flat.extend_from_slice(&mut bytemuck::cast_slice(&[self.len() as u32]));
         flat.extend_from_slice(&mut bytemuck::cast_slice(&[0.0 as u32; 3]));
-        for i in 0..self.len() {
-            flat.extend_from_slice(bytemuck::cast_slice(&[self[i].albedo]));
-            flat.extend_from_slice(bytemuck::cast_slice(&[self[i].type_flag]));
-            flat.extend_from_slice(bytemuck::cast_slice(&[self[i].is_light as u32]));
-            flat.extend_from_slice(bytemuck::cast_slice(&[0 as u32; 3])); // 5, 6, 7
-        }
+        // Matches the layout of Material in types.glsl
+        flat.extend_from_slice(bytemuck::cast_slice(self));
 
         flat
     }
 
     fn bytes_size(&self) -> usize {
-        (std::mem::size_of::<Material>() + 12) * self.len() + 16
+        std::mem::size_of::<Material>() * self.len() + 16
+    }
+}
+
+/// Relative luminance of a linear sRGB color.
+pub fn luminance(c: [f32; 3]) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+/// Linear sRGB color of a black body at `kelvin`, scaled to unit luminance.
+pub fn blackbody(kelvin: f32) -> [f32; 3] {
+    let kelvin = kelvin.max(500.0);
+    // Planck's law integrated against the CIE 1931 observer, using the
+    // multi-lobe fit from Wyman et al. 2013
+    let g = |x: f32, mu: f32, s1: f32, s2: f32| {
+        let t = (x - mu) / if x < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for i in 0..=80 {
+        let lambda = 380.0 + 5.0 * i as f32;
+        let m = lambda as f64 * 1e-9;
+        // The constant factors cancel out in the normalization below
+        let planck = (1.0 / (m.powi(5) * ((1.4388e-2 / (m * kelvin as f64)).exp() - 1.0))) as f32;
+
+        x += planck
+            * (1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7)
+                - 0.065 * g(lambda, 501.1, 20.4, 26.2));
+        y += planck * (0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1));
+        z += planck * (1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8));
+    }
+
+    let rgb = [
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    ];
+    let l = luminance(rgb);
+    [rgb[0] / l, rgb[1] / l, rgb[2] / l]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blackbody() {
+        let candle = blackbody(1900.0);
+        let daylight = blackbody(6500.0);
+        let sky = blackbody(12000.0);
+
+        for c in [candle, daylight, sky].iter() {
+            assert!((luminance(*c) - 1.0).abs() < 1e-4);
+        }
+        assert!(candle[0] > candle[1] && candle[1] > candle[2]);
+        assert!(sky[2] > sky[1] && sky[1] > sky[0]);
+        // Close to the white point
+        for c in daylight.iter() {
+            assert!((c - 1.0).abs() < 0.1, "{:?}", daylight);
+        }
     }
 }
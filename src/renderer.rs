@@ -0,0 +1,460 @@
+use std::fmt;
+
+use wgpu::util::DeviceExt;
+
+use crate::globals::Globals;
+use crate::pipelines::*;
+use crate::scene::{Camera, Scene};
+use crate::texture::Grid;
+use crate::traits::*;
+
+#[derive(Debug)]
+pub enum RenderError {
+    NoAdapter,
+    Device(wgpu::RequestDeviceError),
+    BufferMap(wgpu::BufferAsyncError),
+    UnsupportedFormat(std::path::PathBuf),
+    Image(image::ImageError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::NoAdapter => write!(f, "no suitable GPU adapter found"),
+            RenderError::Device(e) => write!(f, "could not create device: {}", e),
+            RenderError::BufferMap(e) => write!(f, "could not read back output: {}", e),
+            RenderError::UnsupportedFormat(path) => write!(
+                f,
+                "{}: unsupported image format, use .png or .exr",
+                path.display()
+            ),
+            RenderError::Image(e) => write!(f, "could not write image: {}", e),
+            RenderError::Exr(e) => write!(f, "could not write image: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// GPU path tracer accumulating samples into an `Rgba32Float` image, the sum
+/// of all samples so far.
+pub struct Renderer {
+    adapter_info: wgpu::AdapterInfo,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+
+    globals: Globals,
+    samples: u32,
+
+    globals_buffer: wgpu::Buffer,
+    output_texture: wgpu::Texture,
+    output_view: wgpu::TextureView,
+    scene_buffers: SceneBuffers,
+
+    compute_pipeline: compute::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Renderer {
+    /// Renderer on the first suitable adapter, without a window.
+    pub async fn new(scene: &Scene, width: u32, height: u32) -> Result<Self, RenderError> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+            })
+            .await
+            .ok_or(RenderError::NoAdapter)?;
+
+        Self::with_adapter(&adapter, scene, width, height).await
+    }
+
+    /// Use this to share the device with a surface, see `device()`.
+    pub async fn with_adapter(
+        adapter: &wgpu::Adapter,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, RenderError> {
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::default(),
+                    limits: wgpu::Limits::default(),
+                    shader_validation: false,
+                },
+                None,
+            )
+            .await
+            .map_err(RenderError::Device)?;
+
+        // ---- Pipelines ----
+        let compute_pipeline = compute::ComputePipeline::new(&device);
+
+        // ---- Buffers ----
+        let globals = scene.globals(width, height);
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[globals]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let (output_texture, output_view) = create_output(&device, width, height);
+        let scene_buffers = SceneBuffers::new(&device, &queue, scene);
+        let bind_group = create_bind_group(
+            &device,
+            &compute_pipeline,
+            &globals_buffer,
+            &output_view,
+            &scene_buffers,
+        );
+
+        Ok(Renderer {
+            adapter_info: adapter.get_info(),
+            device,
+            queue,
+            globals,
+            samples: 0,
+            globals_buffer,
+            output_texture,
+            output_view,
+            scene_buffers,
+            compute_pipeline,
+            bind_group,
+        })
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Accumulated radiance, divide by `samples()` for the average.
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.output_view
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.globals.window_size;
+        (size.x() as u32, size.y() as u32)
+    }
+
+    /// Number of samples accumulated since the last change.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Uploads the geometry, materials and camera of `scene`.
+    pub fn set_scene(&mut self, scene: &Scene) {
+        let (width, height) = self.size();
+        self.globals = scene.globals(width, height);
+
+        self.scene_buffers = SceneBuffers::new(&self.device, &self.queue, scene);
+        self.rebind();
+    }
+
+    pub fn camera(&self) -> Camera {
+        Camera {
+            look_from: self.globals.look_from,
+            look_at: self.globals.look_at,
+            vfov: self.globals.vfov,
+            aperture: self.globals.aperture,
+            focus_dist: self.globals.focus_dist,
+        }
+    }
+
+    pub fn set_camera(&mut self, camera: &Camera) {
+        let globals = self.globals_mut();
+        globals.look_from = camera.look_from;
+        globals.look_at = camera.look_at;
+        globals.vfov = camera.vfov;
+        globals.aperture = camera.aperture;
+        globals.focus_dist = camera.focus_dist;
+    }
+
+    /// Any change restarts the accumulation.
+    pub fn globals_mut(&mut self) -> &mut Globals {
+        self.samples = 0;
+        &mut self.globals
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let aspect_ratio = width as f32 / height as f32;
+        let globals = self.globals_mut();
+        globals.viewport = glam::Vec2::new(aspect_ratio * 2.0, 2.0);
+        globals.window_size = glam::Vec2::new(width as f32, height as f32);
+        globals.aspect_ratio = aspect_ratio;
+
+        let (output_texture, output_view) = create_output(&self.device, width, height);
+        self.output_texture = output_texture;
+        self.output_view = output_view;
+        self.rebind();
+    }
+
+    /// Traces `samples` more samples per pixel. Each one is its own
+    /// submission to keep dispatches short enough for the driver's watchdog.
+    pub fn accumulate(&mut self, samples: u32) {
+        let (width, height) = self.size();
+
+        for _ in 0..samples {
+            self.globals.rng_seed = rand::random();
+            self.globals.num_frames = self.samples;
+            self.queue.write_buffer(
+                &self.globals_buffer,
+                0,
+                bytemuck::cast_slice(&[self.globals]),
+            );
+
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Accumulate Encoder"),
+                });
+            {
+                let mut compute_pass = encoder.begin_compute_pass();
+                compute_pass.set_pipeline(&self.compute_pipeline.pipeline);
+                compute_pass.set_bind_group(0, &self.bind_group, &[]);
+                compute_pass.dispatch(width.div_ceil(32), height.div_ceil(32), 1);
+            }
+            self.queue.submit(Some(encoder.finish()));
+            self.samples += 1;
+        }
+    }
+
+    /// Average of the accumulated samples, row by row from the top.
+    pub async fn read_back(&self) -> Result<Vec<[f32; 3]>, RenderError> {
+        let (width, height) = self.size();
+        let pixel_size = 4 * std::mem::size_of::<f32>() as u32;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (width * pixel_size).div_ceil(align) * align;
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.output_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &readback_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_row,
+                    rows_per_image: height,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        mapping.await.map_err(RenderError::BufferMap)?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            let scale = 1.0 / self.samples.max(1) as f32;
+            for row in data.chunks(padded_row as usize) {
+                let row: &[[f32; 4]] = bytemuck::cast_slice(&row[..(width * pixel_size) as usize]);
+                pixels.extend(
+                    row.iter()
+                        .map(|p| [p[0] * scale, p[1] * scale, p[2] * scale]),
+                );
+            }
+        }
+        readback_buffer.unmap();
+
+        Ok(pixels)
+    }
+
+    fn rebind(&mut self) {
+        self.samples = 0;
+        self.bind_group = create_bind_group(
+            &self.device,
+            &self.compute_pipeline,
+            &self.globals_buffer,
+            &self.output_view,
+            &self.scene_buffers,
+        );
+    }
+}
+
+fn create_output(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Output texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+/// Everything uploaded from a `Scene`.
+struct SceneBuffers {
+    materials: wgpu::Buffer,
+    bvh: wgpu::Buffer,
+    triangles: wgpu::Buffer,
+    textures: wgpu::Buffer,
+    volumes: wgpu::Buffer,
+    lights: wgpu::Buffer,
+    environment: wgpu::Buffer,
+    grids: wgpu::TextureView,
+    grid_sampler: wgpu::Sampler,
+}
+
+impl SceneBuffers {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) -> Self {
+        let storage = |contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsage::STORAGE,
+            })
+        };
+
+        let bvh = scene.build_bvh();
+        SceneBuffers {
+            materials: storage(&scene.materials.as_bytes()),
+            bvh: storage(&bvh.as_bytes()),
+            triangles: storage(&bvh.triangles().as_bytes()),
+            textures: storage(&scene.textures.as_bytes()),
+            volumes: storage(&bvh.volumes().as_bytes()),
+            lights: storage(&scene.lights().as_bytes()),
+            environment: storage(&scene.environment.as_bytes()),
+            grids: create_grids(device, queue, &Grid::atlas(&scene.textures)),
+            grid_sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Grid sampler"),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Uploads the density grid atlas as an `R32Float` 3D texture.
+fn create_grids(device: &wgpu::Device, queue: &wgpu::Queue, atlas: &Grid) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width: atlas.width,
+        height: atlas.height,
+        depth: atlas.depth,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Grid texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&atlas.values),
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: 4 * atlas.width,
+            rows_per_image: atlas.height,
+        },
+        size,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    compute_pipeline: &compute::ComputePipeline,
+    globals_buffer: &wgpu::Buffer,
+    output_view: &wgpu::TextureView,
+    scene_buffers: &SceneBuffers,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Compute bind group"),
+        layout: &compute_pipeline.bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(globals_buffer.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(output_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.materials.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.bvh.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.triangles.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.textures.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.volumes.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.lights.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::Buffer(scene_buffers.environment.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::TextureView(&scene_buffers.grids),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::Sampler(&scene_buffers.grid_sampler),
+            },
+        ],
+    })
+}
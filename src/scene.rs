@@ -0,0 +1,1664 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::bvh::{self, BuildStrategy, BVH};
+use crate::environment::{Environment, EnvironmentError};
+use crate::geometry::{Light, Sphere, Triangle, Volume, MAX_TRACKING_STEPS, NO_LIGHT};
+use crate::globals::Globals;
+use crate::loaders::{self, gltf, obj, vol};
+use crate::material::{self, Material};
+use crate::sky::{self, Sky};
+use crate::texture::{self, Grid, Texture, MAX_ATLAS_SIZE};
+
+// ---- File format ----
+#[derive(Debug, Deserialize)]
+#[serde(rename = "Scene", deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraDesc,
+    materials: Vec<MaterialDesc>,
+    primitives: Vec<PrimitiveDesc>,
+    #[serde(default)]
+    bvh: BvhDesc,
+    #[serde(default)]
+    fog: Option<FogDesc>,
+    #[serde(default)]
+    integrator: IntegratorDesc,
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    sky: Option<SkyDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    scattering: [f32; 3],
+    #[serde(default)]
+    absorption: [f32; 3],
+    #[serde(default)]
+    anisotropy: f32,
+}
+
+/// Equirectangular `.hdr` or `.exr` image lighting the scene from far away,
+/// turned by `rotation` degrees around +y.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    path: String,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+}
+
+/// Procedural daylight sky with a sun, in place of an environment image.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDesc {
+    sun: SunDesc,
+    #[serde(default = "default_turbidity")]
+    turbidity: f32,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+enum SunDesc {
+    /// Degrees above the horizon and clockwise from north (-z)
+    Angles { elevation: f32, azimuth: f32 },
+    /// Position of the sun seen from a place at a local time
+    Location {
+        latitude: f32,
+        longitude: f32,
+        date: (i32, u32, u32),
+        time: f32,
+        #[serde(default)]
+        utc_offset: f32,
+    },
+}
+
+// Texels of the baked sky, about 0.7° each. Preetham varies slowly, so
+// bilinear lookups only soften the horizon and the glow around the sun; the
+// sun disk is sampled exactly. The table also holds the importance sampling
+// distribution, finer ones cost memory and load time for little gain.
+const SKY_WIDTH: u32 = 512;
+const SKY_HEIGHT: u32 = 256;
+
+impl SkyDesc {
+    fn environment(&self) -> Environment {
+        let sky = match self.sun {
+            SunDesc::Angles { elevation, azimuth } => {
+                Sky::from_angles(elevation, azimuth, self.turbidity)
+            }
+            SunDesc::Location {
+                latitude,
+                longitude,
+                date,
+                time,
+                utc_offset,
+            } => Sky::new(
+                sky::sun_direction(latitude, longitude, date, time, utc_offset),
+                self.turbidity,
+            ),
+        };
+        sky.to_environment(SKY_WIDTH, SKY_HEIGHT)
+            .with_intensity(self.intensity)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntegratorDesc {
+    #[serde(default = "default_max_depth")]
+    max_depth: u32,
+    #[serde(default = "default_roulette_depth")]
+    roulette_depth: u32,
+    #[serde(default)]
+    sampler: Sampler,
+}
+
+fn default_max_depth() -> u32 {
+    16
+}
+
+fn default_roulette_depth() -> u32 {
+    3
+}
+
+impl Default for IntegratorDesc {
+    fn default() -> Self {
+        IntegratorDesc {
+            max_depth: default_max_depth(),
+            roulette_depth: default_roulette_depth(),
+            sampler: Sampler::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_vfov")]
+    vfov: f32,
+    #[serde(default)]
+    aperture: f32,
+    #[serde(default)]
+    focus_dist: Option<f32>,
+}
+
+fn default_vfov() -> f32 {
+    90.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    name: String,
+    albedo: [f32; 3],
+    #[serde(default)]
+    emission: EmissionDesc,
+    /// Principled BSDF parameters, see `Material`
+    #[serde(default)]
+    metallic: f32,
+    #[serde(default = "default_half")]
+    roughness: f32,
+    #[serde(default = "default_half")]
+    specular: f32,
+    #[serde(default)]
+    transmission: f32,
+    #[serde(default)]
+    clearcoat: f32,
+    #[serde(default = "default_clearcoat_gloss")]
+    clearcoat_gloss: f32,
+    #[serde(default)]
+    sheen: f32,
+    #[serde(default = "default_half")]
+    sheen_tint: f32,
+    /// Index of refraction of transmissive materials
+    #[serde(default = "default_ior")]
+    ior: f32,
+    /// Absorption and scattering coefficients per unit length inside
+    /// transmissive materials, with the Henyey-Greenstein `anisotropy` of the
+    /// scattering
+    #[serde(default)]
+    absorption: [f32; 3],
+    #[serde(default)]
+    scattering: [f32; 3],
+    #[serde(default)]
+    anisotropy: f32,
+    /// Textures multiplying the albedo, roughness (green channel) and emission
+    #[serde(default)]
+    albedo_texture: Option<TextureDesc>,
+    #[serde(default)]
+    roughness_texture: Option<TextureDesc>,
+    #[serde(default)]
+    emission_texture: Option<TextureDesc>,
+    /// Tangent space normal map tilted by `normal_scale`, or height map
+    /// (green channel) displacing the surface by up to `bump_scale`.
+    #[serde(default)]
+    normal_texture: Option<TextureDesc>,
+    #[serde(default)]
+    bump_texture: Option<TextureDesc>,
+    #[serde(default = "default_map_scale")]
+    normal_scale: f32,
+    #[serde(default = "default_map_scale")]
+    bump_scale: f32,
+}
+
+fn default_half() -> f32 {
+    0.5
+}
+
+fn default_clearcoat_gloss() -> f32 {
+    1.0
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn default_map_scale() -> f32 {
+    1.0
+}
+
+/// See `Texture`, procedural colors are linear.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+enum TextureDesc {
+    /// Image path relative to the scene file
+    Image(String),
+    Checker {
+        even: [f32; 3],
+        odd: [f32; 3],
+        #[serde(default = "default_texture_scale")]
+        scale: f32,
+    },
+    Noise {
+        low: [f32; 3],
+        high: [f32; 3],
+        #[serde(default = "default_texture_scale")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Marble {
+        low: [f32; 3],
+        high: [f32; 3],
+        #[serde(default = "default_texture_scale")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Gradient {
+        from: [f32; 3],
+        to: [f32; 3],
+        start: [f32; 3],
+        end: [f32; 3],
+    },
+}
+
+fn default_texture_scale() -> f32 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+/// Light emitted by a material, none when omitted. With nits or watts `color`
+/// only sets the chromaticity, `temperature` replaces it with a black body.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmissionDesc {
+    #[serde(default = "default_emission_color")]
+    color: [f32; 3],
+    /// Black body temperature in Kelvin
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default)]
+    unit: LightUnit,
+}
+
+fn default_emission_color() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+impl Default for EmissionDesc {
+    fn default() -> Self {
+        EmissionDesc {
+            color: default_emission_color(),
+            temperature: None,
+            intensity: 0.0,
+            unit: LightUnit::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+enum LightUnit {
+    /// `intensity` scales the color as is
+    #[default]
+    Radiance,
+    /// Luminance in cd/m²
+    Nits,
+    /// Power shared by every surface using the material
+    Watts,
+}
+
+/// Lumens per watt at 555nm, relates luminance to radiance.
+pub(crate) const LUMINOUS_EFFICACY: f32 = 683.0;
+
+impl EmissionDesc {
+    /// Emitted radiance when the material covers `area`.
+    fn radiance(&self, area: f32) -> [f32; 3] {
+        let color = match self.temperature {
+            Some(kelvin) => material::blackbody(kelvin),
+            None => self.color,
+        };
+        let luminance = material::luminance(color);
+        let scale = match self.unit {
+            LightUnit::Radiance => self.intensity,
+            LightUnit::Nits => self.intensity / (LUMINOUS_EFFICACY * luminance),
+            // A diffuse emitter radiates pi * radiance per unit area
+            LightUnit::Watts => self.intensity / (std::f32::consts::PI * area * luminance),
+        };
+
+        match scale.is_finite() {
+            true => [color[0] * scale, color[1] * scale, color[2] * scale],
+            false => [0.0; 3],
+        }
+    }
+}
+
+/// BVH build settings, a 16 bin SAH when omitted.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+enum BvhDesc {
+    Median,
+    Sah {
+        #[serde(default = "default_sah_bins")]
+        bins: usize,
+        #[serde(default = "default_leaf_cost")]
+        leaf_cost: f32,
+    },
+}
+
+fn default_sah_bins() -> usize {
+    16
+}
+
+fn default_leaf_cost() -> f32 {
+    1.0
+}
+
+impl Default for BvhDesc {
+    fn default() -> Self {
+        BvhDesc::Sah {
+            bins: default_sah_bins(),
+            leaf_cost: default_leaf_cost(),
+        }
+    }
+}
+
+impl BvhDesc {
+    fn strategy(&self) -> BuildStrategy {
+        match *self {
+            BvhDesc::Median => BuildStrategy::Median,
+            BvhDesc::Sah { bins, leaf_cost } => BuildStrategy::Sah { bins, leaf_cost },
+        }
+    }
+}
+
+/// A material is referenced either by its name or by its position in `materials`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+enum PrimitiveDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: MaterialRef,
+    },
+    Sphereflake {
+        center: [f32; 3],
+        radius: f32,
+        #[serde(default = "default_flake_axis")]
+        axis: [f32; 3],
+        depth: u32,
+        materials: (MaterialRef, MaterialRef),
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        #[serde(default)]
+        normals: Option<[[f32; 3]; 3]>,
+        #[serde(default)]
+        uvs: Option<[[f32; 2]; 3]>,
+        material: MaterialRef,
+    },
+    /// Wavefront OBJ file, `material` overrides the MTL materials.
+    Mesh {
+        path: String,
+        #[serde(default)]
+        object: Option<String>,
+        #[serde(default)]
+        material: Option<MaterialRef>,
+        #[serde(default)]
+        transform: TransformDesc,
+    },
+    /// glTF 2.0 scene, `use_camera` replaces the scene camera with the first
+    /// camera in the file.
+    Gltf {
+        path: String,
+        #[serde(default)]
+        material: Option<MaterialRef>,
+        #[serde(default)]
+        transform: TransformDesc,
+        #[serde(default)]
+        use_camera: bool,
+    },
+    /// Heterogeneous medium from a Mitsuba `.vol` density grid, filling the
+    /// bounding box stored in the file unless `min` and `max` are given.
+    Volume {
+        path: String,
+        material: MaterialRef,
+        #[serde(default)]
+        min: Option<[f32; 3]>,
+        #[serde(default)]
+        max: Option<[f32; 3]>,
+    },
+}
+
+fn default_flake_axis() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+/// Scale, then rotation (XYZ euler angles in degrees), then translation.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default)]
+    translate: [f32; 3],
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default = "default_scale")]
+    scale: [f32; 3],
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            translate: [0.0; 3],
+            rotate: [0.0; 3],
+            scale: default_scale(),
+        }
+    }
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Mat4 {
+        let [x, y, z] = self.rotate;
+        let rotation = Quat::from_rotation_z(z.to_radians())
+            * Quat::from_rotation_y(y.to_radians())
+            * Quat::from_rotation_x(x.to_radians());
+        Mat4::from_scale_rotation_translation(
+            Vec3::from(self.scale),
+            rotation,
+            Vec3::from(self.translate),
+        )
+    }
+}
+
+// ---- Errors ----
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(ron::de::Error),
+    Obj(obj::ObjError),
+    Gltf(gltf::Error),
+    Volume(vol::VolError),
+    Texture(PathBuf, image::ImageError),
+    Environment(EnvironmentError),
+    SkyAndEnvironment,
+    ZeroMaxDepth,
+    GridAtlasTooLarge([u32; 3]),
+    EmptyVolume(usize),
+    VolumeTooDense {
+        steps: f32,
+        primitive: usize,
+    },
+    DuplicateMaterial(String),
+    NoCamera(PathBuf),
+    UnknownObject {
+        path: PathBuf,
+        name: String,
+        primitive: usize,
+    },
+    UnknownMaterial {
+        name: String,
+        primitive: usize,
+    },
+    MaterialIndexOutOfRange {
+        index: usize,
+        len: usize,
+        primitive: usize,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "could not read scene file: {}", e),
+            SceneError::Parse(e) => write!(f, "could not parse scene file: {}", e),
+            SceneError::Obj(e) => write!(f, "could not load mesh: {}", e),
+            SceneError::Gltf(e) => write!(f, "could not load glTF: {}", e),
+            SceneError::Volume(e) => write!(f, "could not load volume: {}", e),
+            SceneError::Texture(path, e) => {
+                write!(f, "could not load texture {}: {}", path.display(), e)
+            }
+            SceneError::Environment(e) => write!(f, "could not load environment: {}", e),
+            SceneError::SkyAndEnvironment => {
+                write!(f, "a scene has either a sky or an environment, not both")
+            }
+            SceneError::ZeroMaxDepth => write!(f, "max_depth must be a positive integer"),
+            SceneError::GridAtlasTooLarge(size) => write!(
+                f,
+                "volume grids stack up to {}x{}x{} voxels, more than {} on a side",
+                size[0], size[1], size[2], MAX_ATLAS_SIZE
+            ),
+            SceneError::EmptyVolume(primitive) => write!(
+                f,
+                "volume primitive {} needs min below max on every axis",
+                primitive
+            ),
+            SceneError::VolumeTooDense { steps, primitive } => write!(
+                f,
+                "volume primitive {} takes up to {:.0} tracking steps to cross, more than {}",
+                primitive,
+                steps,
+                MAX_TRACKING_STEPS / 2
+            ),
+            SceneError::NoCamera(path) => write!(f, "{} has no perspective camera", path.display()),
+            SceneError::DuplicateMaterial(name) => {
+                write!(f, "material \"{}\" is defined more than once", name)
+            }
+            SceneError::UnknownObject {
+                path,
+                name,
+                primitive,
+            } => write!(
+                f,
+                "primitive {} references object \"{}\" which is not in {}",
+                primitive,
+                name,
+                path.display()
+            ),
+            SceneError::UnknownMaterial { name, primitive } => write!(
+                f,
+                "primitive {} references unknown material \"{}\"",
+                primitive, name
+            ),
+            SceneError::MaterialIndexOutOfRange {
+                index,
+                len,
+                primitive,
+            } => write!(
+                f,
+                "primitive {} references material index {} but only {} materials are defined",
+                primitive, index, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+impl From<ron::de::Error> for SceneError {
+    fn from(e: ron::de::Error) -> Self {
+        SceneError::Parse(e)
+    }
+}
+
+impl From<gltf::Error> for SceneError {
+    fn from(e: gltf::Error) -> Self {
+        SceneError::Gltf(e)
+    }
+}
+
+impl From<obj::ObjError> for SceneError {
+    fn from(e: obj::ObjError) -> Self {
+        SceneError::Obj(e)
+    }
+}
+
+impl From<vol::VolError> for SceneError {
+    fn from(e: vol::VolError) -> Self {
+        SceneError::Volume(e)
+    }
+}
+
+impl From<EnvironmentError> for SceneError {
+    fn from(e: EnvironmentError) -> Self {
+        SceneError::Environment(e)
+    }
+}
+
+// ---- Scene ----
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+}
+
+/// Homogeneous medium filling the space outside of all volumes, none by
+/// default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fog {
+    /// Coefficients per unit length
+    pub scattering: [f32; 3],
+    pub absorption: [f32; 3],
+    /// Henyey-Greenstein asymmetry, positive scatters forward
+    pub anisotropy: f32,
+}
+
+/// Path length limits and sample sequence of the integrator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Integrator {
+    /// Bounces after which every path ends
+    pub max_depth: u32,
+    /// Bounces before Russian roulette starts ending paths that carry little
+    /// energy, at least `max_depth` turns it off
+    pub roulette_depth: u32,
+    pub sampler: Sampler,
+}
+
+/// Numbers each frame draws for a pixel, see sampler.glsl. The low
+/// discrepancy ones spread the samples of a pixel more evenly than chance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Sampler {
+    /// PCG hash of the pixel, frame and dimension
+    Independent = 0,
+    /// Halton points with scrambled digits, independent after 32 dimensions
+    Halton = 1,
+    /// Sobol points with hashed Owen scrambling
+    #[default]
+    Sobol = 2,
+}
+
+impl Default for Integrator {
+    fn default() -> Self {
+        let desc = IntegratorDesc::default();
+        Integrator {
+            max_depth: desc.max_depth,
+            roulette_depth: desc.roulette_depth,
+            sampler: desc.sampler,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Scene {
+    pub camera: Camera,
+    pub fog: Fog,
+    pub integrator: Integrator,
+    /// Radiance of rays that leave the scene, black without one.
+    pub environment: Option<Environment>,
+    pub materials: Vec<Material>,
+    pub textures: Vec<Texture>,
+    pub spheres: Vec<Sphere>,
+    pub triangles: Vec<Triangle>,
+    pub volumes: Vec<Volume>,
+    pub bvh: BuildStrategy,
+}
+
+impl Scene {
+    /// Loads a RON scene, or builds one from a glTF file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gltf") | Some("glb") => return Self::from_gltf(path),
+            _ => {}
+        }
+
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src, path.parent().unwrap_or_else(|| Path::new(".")))
+    }
+
+    /// Mesh paths are relative to `dir`.
+    pub fn parse(src: &str, dir: &Path) -> Result<Self, SceneError> {
+        let desc: SceneDesc = ron::de::from_str(src)?;
+        // Paths without a single bounce would leave the image black
+        if desc.integrator.max_depth == 0 {
+            return Err(SceneError::ZeroMaxDepth);
+        }
+
+        let mut textures = TextureLoader::default();
+        let mut names = HashMap::with_capacity(desc.materials.len());
+        let mut materials = Vec::with_capacity(desc.materials.len());
+        for m in desc.materials.iter() {
+            if names.insert(m.name.as_str(), materials.len()).is_some() {
+                return Err(SceneError::DuplicateMaterial(m.name.clone()));
+            }
+            materials.push(Material {
+                albedo_texture: textures.get(&m.albedo_texture, dir, true)?,
+                roughness_texture: textures.get(&m.roughness_texture, dir, false)?,
+                emission_texture: textures.get(&m.emission_texture, dir, true)?,
+                normal_texture: textures.get(&m.normal_texture, dir, false)?,
+                bump_texture: textures.get(&m.bump_texture, dir, false)?,
+                ..Material::new(m.albedo)
+                    .with_metallic(m.metallic)
+                    .with_roughness(m.roughness)
+                    .with_specular(m.specular)
+                    .with_transmission(m.transmission)
+                    .with_clearcoat(m.clearcoat, m.clearcoat_gloss)
+                    .with_sheen(m.sheen, m.sheen_tint)
+                    .with_ior(m.ior)
+                    .with_absorption(m.absorption)
+                    .with_scattering(m.scattering, m.anisotropy)
+                    .with_normal_scale(m.normal_scale)
+                    .with_bump_scale(m.bump_scale)
+            });
+        }
+        let num_scene_materials = materials.len();
+        let resolve = |r: &MaterialRef, primitive: usize| -> Result<u32, SceneError> {
+            resolve_material(r, &names, num_scene_materials, primitive)
+        };
+
+        // Each OBJ is parsed once, its MTL materials start at the stored offset
+        let mut meshes: HashMap<PathBuf, (obj::Obj, u32)> = HashMap::new();
+        // Grids are uploaded once, with their majorant and the bounds stored
+        // in their file
+        let mut grids: HashMap<PathBuf, (u32, f32, Vec3, Vec3)> = HashMap::new();
+        let mut default_material = None;
+        let mut camera_override = None;
+
+        let mut spheres = Vec::new();
+        let mut triangles = Vec::new();
+        let mut volumes = Vec::new();
+        for (i, p) in desc.primitives.iter().enumerate() {
+            match p {
+                PrimitiveDesc::Sphere {
+                    center,
+                    radius,
+                    material,
+                } => {
+                    let mat = resolve(material, i)?;
+                    spheres.push(Sphere::new(Vec3::from(*center), *radius, mat));
+                }
+                PrimitiveDesc::Sphereflake {
+                    center,
+                    radius,
+                    axis,
+                    depth,
+                    materials,
+                } => {
+                    let mats = (resolve(&materials.0, i)?, resolve(&materials.1, i)?);
+                    spheres.extend(sphereflake(
+                        Vec3::from(*center),
+                        Vec3::from(*axis),
+                        *radius,
+                        0,
+                        *depth,
+                        mats,
+                    ));
+                }
+                PrimitiveDesc::Triangle {
+                    vertices,
+                    normals,
+                    uvs,
+                    material,
+                } => {
+                    let mat = resolve(material, i)?;
+                    let v = [
+                        Vec3::from(vertices[0]),
+                        Vec3::from(vertices[1]),
+                        Vec3::from(vertices[2]),
+                    ];
+                    let t = match normals {
+                        Some(n) => Triangle::with_normals(
+                            v,
+                            [
+                                Vec3::from(n[0]).normalize(),
+                                Vec3::from(n[1]).normalize(),
+                                Vec3::from(n[2]).normalize(),
+                            ],
+                            mat,
+                        ),
+                        None => Triangle::new(v, mat),
+                    };
+                    triangles.push(match uvs {
+                        Some(uv) => {
+                            t.with_uvs([Vec2::from(uv[0]), Vec2::from(uv[1]), Vec2::from(uv[2])])
+                        }
+                        None => t,
+                    });
+                }
+                PrimitiveDesc::Mesh {
+                    path,
+                    object,
+                    material,
+                    transform,
+                } => {
+                    let override_mat = match material {
+                        Some(m) => Some(resolve(m, i)?),
+                        None => None,
+                    };
+
+                    let path = dir.join(path);
+                    if !meshes.contains_key(&path) {
+                        let mesh = obj::load(&path)?;
+                        let offset = materials.len() as u32;
+                        for m in mesh.materials.iter() {
+                            materials.push(Material {
+                                albedo_texture: textures.load(m.diffuse_map.clone(), true)?,
+                                roughness_texture: textures.load(m.roughness_map.clone(), false)?,
+                                emission_texture: textures.load(m.emission_map.clone(), true)?,
+                                normal_texture: textures.load(m.normal_map.clone(), false)?,
+                                bump_texture: textures.load(m.bump_map.clone(), false)?,
+                                ..m.to_material()
+                            });
+                        }
+                        meshes.insert(path.clone(), (mesh, offset));
+                    }
+                    let (mesh, offset) = &meshes[&path];
+
+                    let objects: Vec<&obj::Object> = match object {
+                        Some(name) => match mesh.objects.iter().find(|o| &o.name == name) {
+                            Some(o) => vec![o],
+                            None => {
+                                return Err(SceneError::UnknownObject {
+                                    path,
+                                    name: name.clone(),
+                                    primitive: i,
+                                })
+                            }
+                        },
+                        None => mesh.objects.iter().collect(),
+                    };
+
+                    let m = transform.matrix();
+                    for o in objects {
+                        for t in o.triangles.iter() {
+                            let mat_index = mesh_material(
+                                t.mat_index,
+                                override_mat,
+                                *offset,
+                                &mut default_material,
+                                &mut materials,
+                            );
+                            triangles.push(Triangle {
+                                mat_index,
+                                ..t.transform(&m)
+                            });
+                        }
+                    }
+                }
+                PrimitiveDesc::Gltf {
+                    path,
+                    material,
+                    transform,
+                    use_camera,
+                } => {
+                    let override_mat = match material {
+                        Some(m) => Some(resolve(m, i)?),
+                        None => None,
+                    };
+
+                    let path = dir.join(path);
+                    let gltf = gltf::load(&path)?;
+                    let offset = materials.len() as u32;
+                    let texture_offset = textures.textures.len() as u32;
+                    materials.extend(
+                        gltf.materials
+                            .iter()
+                            .map(|m| m.with_texture_offset(texture_offset)),
+                    );
+                    textures.textures.extend(gltf.textures);
+
+                    let m = transform.matrix();
+                    for t in gltf.triangles.iter() {
+                        let mat_index = mesh_material(
+                            t.mat_index,
+                            override_mat,
+                            offset,
+                            &mut default_material,
+                            &mut materials,
+                        );
+                        triangles.push(Triangle {
+                            mat_index,
+                            ..t.transform(&m)
+                        });
+                    }
+
+                    if *use_camera {
+                        let c = gltf.camera.ok_or(SceneError::NoCamera(path))?;
+                        camera_override = Some(Camera {
+                            look_from: m.transform_point3(c.look_from),
+                            look_at: m.transform_point3(c.look_at),
+                            ..c
+                        });
+                    }
+                }
+                PrimitiveDesc::Volume {
+                    path,
+                    material,
+                    min,
+                    max,
+                } => {
+                    let mat = resolve(material, i)?;
+
+                    let path = dir.join(path);
+                    if !grids.contains_key(&path) {
+                        let v = vol::load(&path)?;
+                        let majorant = v.grid.max();
+                        textures.textures.push(Texture::Grid(v.grid));
+                        let index = textures.textures.len() as u32 - 1;
+                        grids.insert(path.clone(), (index, majorant, v.min, v.max));
+                    }
+                    let (density, majorant, file_min, file_max) = grids[&path];
+                    let min = min.map_or(file_min, Vec3::from);
+                    let max = max.map_or(file_max, Vec3::from);
+                    // A flat box has no interior to map the grid onto
+                    if !min.cmplt(max).all() {
+                        return Err(SceneError::EmptyVolume(i));
+                    }
+                    let volume = Volume::new(min, max, density, mat);
+
+                    // Crossing the box diagonal takes this many tracking steps
+                    // on average. At half the cap running out of steps is
+                    // vanishingly rare.
+                    let m = &materials[mat as usize];
+                    let sigma_t = Vec3::from(m.absorption) + Vec3::from(m.scattering);
+                    let steps =
+                        majorant * sigma_t.max_element() * (volume.max - volume.min).length();
+                    if steps > (MAX_TRACKING_STEPS / 2) as f32 {
+                        return Err(SceneError::VolumeTooDense {
+                            steps,
+                            primitive: i,
+                        });
+                    }
+                    volumes.push(volume);
+                }
+            }
+        }
+
+        // All grids share one 3D texture
+        let atlas_size = Grid::atlas_size(&textures.textures);
+        if atlas_size.iter().any(|&s| s > MAX_ATLAS_SIZE) {
+            return Err(SceneError::GridAtlasTooLarge(atlas_size));
+        }
+
+        // Emitters given in watts spread their power over all their surfaces
+        let mut areas = vec![0.0; num_scene_materials];
+        let sphere_areas = spheres.iter().map(|s| (s.mat_index, s.area()));
+        let triangle_areas = triangles.iter().map(|t| (t.mat_index, t.area()));
+        for (mat, area) in sphere_areas.chain(triangle_areas) {
+            if let Some(a) = areas.get_mut(mat as usize) {
+                *a += area;
+            }
+        }
+        for (i, m) in desc.materials.iter().enumerate() {
+            materials[i].emission = m.emission.radiance(areas[i]);
+        }
+
+        let c = &desc.camera;
+        let look_from = Vec3::from(c.look_from);
+        let look_at = Vec3::from(c.look_at);
+        let camera = camera_override.unwrap_or(Camera {
+            look_from,
+            look_at,
+            vfov: c.vfov,
+            aperture: c.aperture,
+            focus_dist: c
+                .focus_dist
+                .unwrap_or_else(|| (look_from - look_at).length()),
+        });
+
+        // Emissive spheres are sampled directly, see `Scene::lights`
+        let mut num_lights = 0;
+        for s in spheres.iter_mut() {
+            if materials[s.mat_index as usize].is_light() {
+                s.light_index = num_lights;
+                num_lights += 1;
+            }
+        }
+
+        let fog = desc.fog.map_or(Fog::default(), |f| Fog {
+            scattering: f.scattering,
+            absorption: f.absorption,
+            anisotropy: f.anisotropy.clamp(-0.99, 0.99),
+        });
+
+        let integrator = Integrator {
+            max_depth: desc.integrator.max_depth,
+            roulette_depth: desc.integrator.roulette_depth,
+            sampler: desc.integrator.sampler,
+        };
+
+        let environment = match (desc.environment, desc.sky) {
+            (Some(_), Some(_)) => return Err(SceneError::SkyAndEnvironment),
+            (Some(e), None) => Some(
+                Environment::load(dir.join(&e.path))?
+                    .with_rotation(e.rotation)
+                    .with_intensity(e.intensity),
+            ),
+            (None, Some(s)) => Some(s.environment()),
+            (None, None) => None,
+        };
+
+        Ok(Scene {
+            camera,
+            fog,
+            integrator,
+            environment,
+            materials,
+            textures: textures.textures,
+            spheres,
+            triangles,
+            volumes,
+            bvh: desc.bvh.strategy(),
+        })
+    }
+
+    /// Scene made of a single glTF file. Without a camera in the file the
+    /// view is framed on the bounds of the geometry.
+    pub fn from_gltf<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let gltf = gltf::load(path)?;
+
+        let mut materials = gltf.materials;
+        let mut default_material = None;
+        let mut triangles = gltf.triangles;
+        for t in triangles.iter_mut() {
+            t.mat_index =
+                mesh_material(t.mat_index, None, 0, &mut default_material, &mut materials);
+        }
+
+        let camera = match gltf.camera {
+            Some(c) => c,
+            None => {
+                let (min, max) = triangles.iter().flat_map(|t| t.vertices.iter()).fold(
+                    (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                    |(min, max), v| (min.min(*v), max.max(*v)),
+                );
+                let (center, radius) = match triangles.is_empty() {
+                    true => (Vec3::zero(), 1.0),
+                    false => (0.5 * (min + max), 0.5 * (max - min).length()),
+                };
+                let look_from = center + Vec3::new(0.0, 0.5, -2.0) * radius;
+                Camera {
+                    look_from,
+                    look_at: center,
+                    vfov: 60.0,
+                    aperture: 0.0,
+                    focus_dist: (look_from - center).length(),
+                }
+            }
+        };
+
+        Ok(Scene {
+            camera,
+            fog: Fog::default(),
+            integrator: Integrator::default(),
+            environment: None,
+            materials,
+            textures: gltf.textures,
+            spheres: Vec::new(),
+            triangles,
+            volumes: Vec::new(),
+            bvh: BuildStrategy::default(),
+        })
+    }
+
+    /// BVH over every primitive, cached on disk between runs.
+    pub fn build_bvh(&self) -> BVH {
+        BVH::from_primitives_cached(
+            &self.spheres,
+            &self.triangles,
+            &self.volumes,
+            self.bvh,
+            &bvh::cache_dir(),
+        )
+    }
+
+    /// Emissive spheres in the order of their `light_index`.
+    pub fn lights(&self) -> Vec<Light> {
+        let mut spheres: Vec<&Sphere> = self
+            .spheres
+            .iter()
+            .filter(|s| s.light_index != NO_LIGHT)
+            .collect();
+        spheres.sort_by_key(|s| s.light_index);
+        spheres
+            .iter()
+            .map(|s| Light {
+                center: s.center,
+                radius: s.radius,
+                mat_index: s.mat_index,
+            })
+            .collect()
+    }
+
+    pub fn globals(&self, width: u32, height: u32) -> Globals {
+        let viewport_height = 2.0;
+        let ar = width as f32 / height as f32;
+        Globals {
+            look_from: self.camera.look_from,
+            vfov: self.camera.vfov,
+            look_at: self.camera.look_at,
+            aspect_ratio: ar,
+            aperture: self.camera.aperture,
+            focus_dist: self.camera.focus_dist,
+            viewport: Vec2::new(ar * viewport_height, viewport_height),
+            window_size: Vec2::new(width as f32, height as f32),
+            rng_seed: rand::random(),
+            num_frames: 0,
+            fog_scattering: self.fog.scattering.into(),
+            fog_anisotropy: self.fog.anisotropy,
+            fog_absorption: self.fog.absorption.into(),
+            max_depth: self.integrator.max_depth,
+            roulette_depth: self.integrator.roulette_depth,
+            sampler_kind: self.integrator.sampler as u32,
+            pad0: [0; 2],
+        }
+    }
+}
+
+/// Loads each image once per color space.
+#[derive(Default)]
+struct TextureLoader {
+    textures: Vec<Texture>,
+    indices: HashMap<(PathBuf, bool), u32>,
+}
+
+impl TextureLoader {
+    /// Index of the texture described by `desc`, images are relative to `dir`.
+    fn get(
+        &mut self,
+        desc: &Option<TextureDesc>,
+        dir: &Path,
+        srgb: bool,
+    ) -> Result<u32, SceneError> {
+        let texture = match desc {
+            None => return Ok(texture::NO_TEXTURE),
+            Some(TextureDesc::Image(path)) => return self.load(Some(dir.join(path)), srgb),
+            Some(TextureDesc::Checker { even, odd, scale }) => Texture::Checker {
+                even: *even,
+                odd: *odd,
+                scale: *scale,
+            },
+            Some(TextureDesc::Noise {
+                low,
+                high,
+                scale,
+                octaves,
+            }) => Texture::Noise {
+                low: *low,
+                high: *high,
+                scale: *scale,
+                octaves: *octaves,
+            },
+            Some(TextureDesc::Marble {
+                low,
+                high,
+                scale,
+                octaves,
+            }) => Texture::Marble {
+                low: *low,
+                high: *high,
+                scale: *scale,
+                octaves: *octaves,
+            },
+            Some(TextureDesc::Gradient {
+                from,
+                to,
+                start,
+                end,
+            }) => Texture::Gradient {
+                from: *from,
+                to: *to,
+                start: *start,
+                end: *end,
+            },
+        };
+        self.textures.push(texture);
+        Ok(self.textures.len() as u32 - 1)
+    }
+
+    /// Index of the texture at `path`, `NO_TEXTURE` without a path.
+    fn load(&mut self, path: Option<PathBuf>, srgb: bool) -> Result<u32, SceneError> {
+        let path = match path {
+            Some(p) => p,
+            None => return Ok(texture::NO_TEXTURE),
+        };
+        if let Some(index) = self.indices.get(&(path.clone(), srgb)) {
+            return Ok(*index);
+        }
+
+        let texture =
+            Texture::load(&path, srgb).map_err(|e| SceneError::Texture(path.clone(), e))?;
+        self.textures.push(texture);
+        let index = self.textures.len() as u32 - 1;
+        self.indices.insert((path, srgb), index);
+        Ok(index)
+    }
+}
+
+/// Global material index of an imported triangle, faces without a material get
+/// a shared grey default.
+fn mesh_material(
+    mat_index: u32,
+    override_mat: Option<u32>,
+    offset: u32,
+    default_material: &mut Option<u32>,
+    materials: &mut Vec<Material>,
+) -> u32 {
+    match (override_mat, mat_index) {
+        (Some(mat), _) => mat,
+        (None, loaders::NO_MATERIAL) => *default_material.get_or_insert_with(|| {
+            materials.push(Material::new([0.8; 3]));
+            materials.len() as u32 - 1
+        }),
+        (None, mat) => mat + offset,
+    }
+}
+
+fn resolve_material(
+    r: &MaterialRef,
+    names: &HashMap<&str, usize>,
+    len: usize,
+    primitive: usize,
+) -> Result<u32, SceneError> {
+    match r {
+        MaterialRef::Index(index) if *index < len => Ok(*index as u32),
+        MaterialRef::Index(index) => Err(SceneError::MaterialIndexOutOfRange {
+            index: *index,
+            len,
+            primitive,
+        }),
+        MaterialRef::Name(name) => match names.get(name.as_str()) {
+            Some(index) => Ok(*index as u32),
+            None => Err(SceneError::UnknownMaterial {
+                name: name.clone(),
+                primitive,
+            }),
+        },
+    }
+}
+
+fn sphereflake(
+    pos: Vec3,
+    axis: Vec3,
+    r: f32,
+    depth: u32,
+    max_depth: u32,
+    mats: (u32, u32),
+) -> Vec<Sphere> {
+    use std::f32::consts::{FRAC_PI_4, FRAC_PI_6, PI};
+
+    let mat = match depth % 2 {
+        0 => mats.0,
+        _ => mats.1,
+    };
+    let mut s = vec![Sphere::new(pos, r, mat)];
+
+    if depth == max_depth {
+        return s;
+    }
+
+    let perp: Vec3;
+    if axis.x() != 0.0 {
+        perp = Vec3::new(-axis.y(), axis.x(), 0.0).normalize();
+    } else if axis.y() != 0.0 {
+        perp = Vec3::new(axis.y(), -axis.x(), 0.0).normalize();
+    } else {
+        perp = Vec3::new(axis.z(), 0.0, -axis.x()).normalize();
+    };
+
+    // Vertical
+    for i in 1..3 {
+        let mat = glam::Mat3::from_axis_angle(perp, FRAC_PI_4 * i as f32);
+        let a1 = mat * axis.normalize();
+        let n_spheres = match i % 2 {
+            1 => 3,
+            _ => 6,
+        };
+        let angle = 2.0 * PI / (n_spheres) as f32;
+        // Around
+        for j in 0..n_spheres {
+            let offset = match i % 2 {
+                1 => 0.0,
+                _ => FRAC_PI_6,
+            };
+            let mat = glam::Mat3::from_axis_angle(axis, angle * j as f32 + offset);
+            let new_axis = (mat * a1).normalize();
+            let new_pos = pos + new_axis * (r) * 1.33;
+            s.extend(sphereflake(
+                new_pos,
+                new_axis,
+                0.33 * r,
+                depth + 1,
+                max_depth,
+                mats,
+            ));
+        }
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"
+        Scene(
+            camera: (look_from: (0.0, 1.0, -2.0), look_at: (0.0, 1.0, 0.0)),
+            materials: [
+                (name: "ground", albedo: (0.5, 0.5, 0.5)),
+                (name: "light", albedo: (0.0, 0.0, 0.0), emission: (intensity: 4.0)),
+                (name: "mirror", albedo: (1.0, 1.0, 1.0), metallic: 1.0, roughness: 0.3, clearcoat: 0.5),
+            ],
+            primitives: [
+                Sphere(center: (0.0, -100.0, 0.0), radius: 100.0, material: "ground"),
+                Sphere(center: (0.0, 5.0, 0.0), radius: 1.0, material: 1),
+                Sphereflake(center: (0.0, 1.0, 0.0), radius: 1.0, depth: 1, materials: ("mirror", 0)),
+                Triangle(vertices: ((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)), material: "ground"),
+            ],
+        )
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let scene = Scene::parse(SCENE, Path::new(".")).unwrap();
+
+        assert_eq!(scene.materials.len(), 3);
+        assert!(!scene.materials[0].is_light());
+        assert_eq!(scene.materials[1].emission, [4.0; 3]);
+        assert_eq!(scene.materials[0].roughness, 0.5);
+        assert_eq!(scene.materials[2].metallic, 1.0);
+        assert_eq!(scene.materials[2].roughness, 0.3);
+        assert_eq!(scene.materials[2].clearcoat, 0.5);
+
+        // 2 spheres + a flake of depth 1 (1 + 9 children)
+        assert_eq!(scene.spheres.len(), 12);
+        assert_eq!(scene.spheres[1].mat_index, 1);
+        assert_eq!(scene.spheres[2].mat_index, 2);
+        assert_eq!(scene.spheres[3].mat_index, 0);
+
+        assert_eq!(scene.triangles.len(), 1);
+        assert_eq!(scene.triangles[0].normals[0], -Vec3::unit_y());
+
+        assert_eq!(scene.camera.vfov, 90.0);
+        assert_eq!(scene.camera.focus_dist, 2.0);
+        assert_eq!(scene.bvh, BuildStrategy::default());
+        assert_eq!(scene.integrator, Integrator::default());
+
+        let src = SCENE.replace(
+            "primitives:",
+            "bvh: Sah(bins: 8), integrator: (max_depth: 64, sampler: Halton), primitives:",
+        );
+        let scene = Scene::parse(&src, Path::new(".")).unwrap();
+        assert_eq!(
+            scene.bvh,
+            BuildStrategy::Sah {
+                bins: 8,
+                leaf_cost: 1.0
+            }
+        );
+        assert_eq!(
+            scene.integrator,
+            Integrator {
+                max_depth: 64,
+                roulette_depth: 3,
+                sampler: Sampler::Halton
+            }
+        );
+
+        let src = SCENE.replace("primitives:", "integrator: (max_depth: 0), primitives:");
+        assert!(matches!(
+            Scene::parse(&src, Path::new(".")),
+            Err(SceneError::ZeroMaxDepth)
+        ));
+
+        // Misspelled and retired keys are errors rather than ignored
+        for (from, to) in [
+            ("metallic: 1.0", "metalic: 1.0"),
+            ("roughness: 0.3", "fuzz: 0.3"),
+            ("radius: 1.0, depth", "radius: 1.0, deph"),
+            ("look_at:", "lookat:"),
+        ]
+        .iter()
+        {
+            let src = SCENE.replace(from, to);
+            assert!(matches!(
+                Scene::parse(&src, Path::new(".")),
+                Err(SceneError::Parse(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_emission() {
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 1.0, -2.0), look_at: (0.0, 1.0, 0.0)),
+                materials: [
+                    (name: "panel", albedo: (0.5, 0.5, 0.5), emission: (color: (0.0, 1.0, 0.0), intensity: 683.0, unit: Nits)),
+                    (name: "bulb", albedo: (0.0, 0.0, 0.0), emission: (intensity: 100.0, unit: Watts)),
+                    (name: "candle", albedo: (0.0, 0.0, 0.0), emission: (temperature: Some(1900.0), intensity: 2.0)),
+                ],
+                primitives: [
+                    Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "bulb"),
+                    Sphere(center: (0.0, 3.0, 0.0), radius: 1.0, material: "bulb"),
+                ],
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new(".")).unwrap();
+        let close = |a: [f32; 3], b: [f32; 3]| (0..3).all(|i| (a[i] - b[i]).abs() < 1e-4);
+
+        // One nit of pure green
+        assert!(close(scene.materials[0].emission, [0.0, 1.0 / 0.7152, 0.0]));
+        assert_eq!(scene.materials[0].albedo, [0.5; 3]);
+
+        // 100W over two unit spheres
+        let radiance = 100.0 / (std::f32::consts::PI * 8.0 * std::f32::consts::PI);
+        assert!(close(scene.materials[1].emission, [radiance; 3]));
+
+        let candle = scene.materials[2].emission;
+        assert!((material::luminance(candle) - 2.0).abs() < 1e-4);
+        assert!(candle[0] > candle[2]);
+    }
+
+    #[test]
+    fn test_sky() {
+        let src = SCENE.replace(
+            "primitives:",
+            "sky: Some((sun: Angles(elevation: 30.0, azimuth: 180.0), intensity: 0.5)), primitives:",
+        );
+        let scene = Scene::parse(&src, Path::new(".")).unwrap();
+        let environment = scene.environment.unwrap();
+        assert_eq!((environment.width, environment.height), (512, 256));
+        assert_eq!(environment.intensity, 0.5);
+        let sun = environment.sun.unwrap().direction;
+        assert!((sun.y() - 0.5).abs() < 1e-5 && sun.z() > 0.0);
+
+        let src = SCENE.replace(
+            "primitives:",
+            "sky: Some((sun: Location(latitude: 40.7, longitude: -74.0, date: (2021, 9, 1), time: 14.5, utc_offset: -4.0))), primitives:",
+        );
+        let scene = Scene::parse(&src, Path::new(".")).unwrap();
+        assert!(scene.environment.unwrap().sun.is_some());
+
+        let src = SCENE.replace(
+            "primitives:",
+            "environment: Some((path: \"sky.hdr\")), sky: Some((sun: Angles(elevation: 30.0, azimuth: 0.0))), primitives:",
+        );
+        assert!(matches!(
+            Scene::parse(&src, Path::new(".")),
+            Err(SceneError::SkyAndEnvironment)
+        ));
+    }
+
+    #[test]
+    fn test_procedural_texture() {
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 1.0, -2.0), look_at: (0.0, 1.0, 0.0)),
+                materials: [
+                    (name: "floor", albedo: (1.0, 1.0, 1.0), albedo_texture: Some(Checker(even: (1.0, 1.0, 1.0), odd: (0.0, 0.0, 0.0)))),
+                    (name: "stone", albedo: (1.0, 1.0, 1.0), metallic: 1.0, roughness_texture: Some(Noise(low: (0.0, 0.0, 0.0), high: (1.0, 1.0, 1.0), scale: 4.0))),
+                ],
+                primitives: [Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "floor")],
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new(".")).unwrap();
+
+        assert_eq!(scene.textures.len(), 2);
+        assert_eq!(scene.materials[0].albedo_texture, 0);
+        assert_eq!(scene.materials[1].roughness_texture, 1);
+        assert_eq!(scene.materials[1].albedo_texture, texture::NO_TEXTURE);
+        match scene.textures[1] {
+            Texture::Noise { scale, octaves, .. } => assert_eq!((scale, octaves), (4.0, 4)),
+            ref t => panic!("{:?}", t),
+        }
+    }
+
+    #[test]
+    fn test_unknown_material() {
+        let src = SCENE.replace("material: \"ground\"", "material: \"grass\"");
+        match Scene::parse(&src, Path::new(".")) {
+            Err(SceneError::UnknownMaterial { name, primitive }) => {
+                assert_eq!(name, "grass");
+                assert_eq!(primitive, 0);
+            }
+            r => panic!("expected UnknownMaterial, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_material_index_out_of_range() {
+        let src = SCENE.replace("material: 1", "material: 3");
+        match Scene::parse(&src, Path::new(".")) {
+            Err(SceneError::MaterialIndexOutOfRange {
+                index,
+                len,
+                primitive,
+            }) => {
+                assert_eq!((index, len, primitive), (3, 3, 1));
+            }
+            r => panic!("expected MaterialIndexOutOfRange, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_default_scene() {
+        let scene = Scene::load("scenes/default.ron").unwrap();
+        assert_eq!(scene.spheres.len(), 2 + 1 + 9 + 81 + 729);
+
+        let scene = Scene::load("scenes/cornell.ron").unwrap();
+        assert_eq!(scene.triangles.len(), 12);
+    }
+
+    #[test]
+    fn test_mesh() {
+        let scene = Scene::load("scenes/mesh.ron").unwrap();
+
+        // 2 cubes from the same file share their MTL materials
+        assert_eq!(scene.triangles.len(), 24);
+        assert_eq!(scene.materials.len(), 3 + 2);
+        assert_eq!(scene.triangles[0].mat_index, 3);
+        assert_eq!(scene.triangles[12].mat_index, 1);
+
+        // Second cube is scaled by 0.5 and moved to x = 1.5
+        let v = scene.triangles[12..]
+            .iter()
+            .flat_map(|t| t.vertices.iter())
+            .fold(Vec3::splat(-1e9), |a, b| a.max(*b));
+        assert_eq!(v, Vec3::new(2.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_volume() {
+        // Unit density grids of 1 x 1 x depth voxels in the box from -1 to 1
+        let vol = |depth: i32| {
+            let mut bytes = b"VOL\x03".to_vec();
+            for i in [1, 1, 1, depth, 1].iter() {
+                bytes.extend_from_slice(&i.to_le_bytes());
+            }
+            for f in [-1.0f32, -1.0, -1.0, 1.0, 1.0, 1.0].iter() {
+                bytes.extend_from_slice(&f.to_le_bytes());
+            }
+            for _ in 0..depth {
+                bytes.extend_from_slice(&1.0f32.to_le_bytes());
+            }
+            bytes
+        };
+        let dir = std::env::temp_dir().join(format!("volume-scene-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("small.vol"), vol(2)).unwrap();
+        fs::write(dir.join("deep.vol"), vol(MAX_ATLAS_SIZE as i32 - 1)).unwrap();
+
+        let parse = |volumes: &str| {
+            let src = SCENE
+                .replace(
+                    "(name: \"mirror\"",
+                    "(name: \"smoke\", albedo: (1.0, 1.0, 1.0), scattering: (40.0, 40.0, 40.0)),
+                     (name: \"mirror\"",
+                )
+                .replace("primitives: [", &format!("primitives: [{}", volumes));
+            Scene::parse(&src, &dir)
+        };
+        let small = parse(r#"Volume(path: "small.vol", material: "ground"),"#);
+        let deep = parse(r#"Volume(path: "deep.vol", material: "ground"),"#);
+        let both = parse(
+            r#"Volume(path: "deep.vol", material: "ground"), Volume(path: "small.vol", material: "ground"),"#,
+        );
+        // About 40 * 3.5 steps across the diagonal, 40 * 0.5 across a slab
+        let smoke = parse(r#"Volume(path: "small.vol", material: "smoke"),"#);
+        let slab = parse(
+            r#"Volume(path: "small.vol", material: "smoke", min: Some((0.0, 0.0, 0.0)), max: Some((0.5, 0.01, 0.01))),"#,
+        );
+        let flat =
+            parse(r#"Volume(path: "small.vol", material: "ground", max: Some((1.0, -1.0, 1.0))),"#);
+        let inverted =
+            parse(r#"Volume(path: "small.vol", material: "ground", min: Some((2.0, 2.0, 2.0))),"#);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(small.unwrap().volumes.len(), 1);
+        assert_eq!(deep.unwrap().volumes.len(), 1);
+        // Together the grids no longer fit in one 3D texture
+        assert!(matches!(
+            both,
+            Err(SceneError::GridAtlasTooLarge([1, 1, 2049]))
+        ));
+        assert!(matches!(
+            smoke,
+            Err(SceneError::VolumeTooDense { primitive: 0, .. })
+        ));
+        assert_eq!(slab.unwrap().volumes.len(), 1);
+        assert!(matches!(flat, Err(SceneError::EmptyVolume(0))));
+        assert!(matches!(inverted, Err(SceneError::EmptyVolume(0))));
+    }
+
+    #[test]
+    fn test_gltf() {
+        let scene = Scene::load("scenes/meshes/quad.gltf").unwrap();
+        assert_eq!(scene.triangles.len(), 2);
+        assert_eq!(scene.materials.len(), 2);
+        assert_eq!(scene.camera.look_from, Vec3::new(0.0, 2.0, 5.0));
+
+        let src = r#"
+            Scene(
+                camera: (look_from: (0.0, 1.0, -2.0), look_at: (0.0, 1.0, 0.0)),
+                materials: [(name: "ground", albedo: (0.5, 0.5, 0.5))],
+                primitives: [
+                    Gltf(
+                        path: "quad.gltf",
+                        transform: (translate: (1.0, 0.0, 0.0)),
+                        use_camera: true,
+                    ),
+                ],
+            )
+        "#;
+        let scene = Scene::parse(src, Path::new("scenes/meshes")).unwrap();
+        assert_eq!(scene.materials.len(), 3);
+        assert_eq!(scene.triangles[0].mat_index, 1);
+        assert_eq!(scene.camera.look_from, Vec3::new(1.0, 2.0, 5.0));
+    }
+}
@@ -0,0 +1,286 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::environment::{Environment, Sun};
+use crate::scene::LUMINOUS_EFFICACY;
+
+/// Half of the apparent diameter of the sun in radians.
+pub const SUN_ANGULAR_RADIUS: f32 = 0.004_65;
+
+// Luminance of the sun outside of the atmosphere in cd/m²
+const SUN_LUMINANCE: f32 = 1.96e9;
+
+/// Clear daylight sky of Preetham et al., "A Practical Analytic Model for
+/// Daylight", with a sun disk dimmed by the air it shines through. Radiance
+/// is in the units of `LightUnit::Nits`, black below the horizon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    /// Towards the sun, +y is up and -z is north.
+    pub sun_direction: Vec3,
+    /// Haze of the air from 2 (very clear) to 10 (hazy).
+    pub turbidity: f32,
+}
+
+// Perez coefficients A to E as a * turbidity + b
+type Perez = [[f32; 2]; 5];
+
+const PEREZ_Y: Perez = [
+    [0.1787, -1.4630],
+    [-0.3554, 0.4275],
+    [-0.0227, 5.3251],
+    [0.1206, -2.5771],
+    [-0.0670, 0.3703],
+];
+const PEREZ_X: Perez = [
+    [-0.0193, -0.2592],
+    [-0.0665, 0.0008],
+    [-0.0004, 0.2125],
+    [-0.0641, -0.8989],
+    [-0.0033, 0.0452],
+];
+const PEREZ_LOWER_Y: Perez = [
+    [-0.0167, -0.2608],
+    [-0.0950, 0.0092],
+    [-0.0079, 0.2102],
+    [-0.0441, -1.6537],
+    [-0.0109, 0.0529],
+];
+
+// Zenith chromaticity as turbidity², turbidity and 1 rows against the sun's
+// zenith angle cubed, squared, as is and 1
+const ZENITH_X: [[f32; 4]; 3] = [
+    [0.00166, -0.00375, 0.00209, 0.0],
+    [-0.02903, 0.06377, -0.03202, 0.00394],
+    [0.11693, -0.21196, 0.06052, 0.25886],
+];
+const ZENITH_Y: [[f32; 4]; 3] = [
+    [0.00275, -0.00610, 0.00317, 0.0],
+    [-0.04214, 0.08970, -0.04153, 0.00516],
+    [0.15346, -0.26756, 0.06670, 0.26688],
+];
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        Sky {
+            sun_direction: sun_direction.normalize(),
+            turbidity: turbidity.clamp(1.7, 10.0),
+        }
+    }
+
+    /// Sun `elevation` degrees above the horizon and `azimuth` degrees
+    /// clockwise from north (-z) towards east (+x).
+    pub fn from_angles(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        Sky::new(direction, turbidity)
+    }
+
+    // The model does not cover twilight, a sun below the horizon lights the
+    // sky as if it was setting
+    fn sun_zenith(&self) -> f32 {
+        self.sun_direction.y().clamp(-1.0, 1.0).acos().min(0.5 * PI)
+    }
+
+    /// Linear RGB radiance of the sky in direction `d`, without the sun.
+    pub fn radiance(&self, d: Vec3) -> [f32; 3] {
+        let d = d.normalize();
+        if d.y() <= 0.0 {
+            return [0.0; 3];
+        }
+
+        let t = self.turbidity;
+        let theta_s = self.sun_zenith();
+        let cos_theta = d.y().max(0.01);
+        let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith = |c: &[[f32; 4]; 3]| {
+            let angles = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: &[f32; 4]| r.iter().zip(angles.iter()).map(|(a, b)| a * b).sum::<f32>();
+            t * t * row(&c[0]) + t * row(&c[1]) + row(&c[2])
+        };
+        let relative = |c: &Perez| {
+            let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(|i| c[i][0] * t + c[i][1]);
+            let f = |cos_theta: f32, gamma: f32| {
+                (1.0 + a * (b / cos_theta).exp())
+                    * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+            };
+            f(cos_theta, gamma) / f(1.0, theta_s)
+        };
+
+        // kcd/m² in xyY to radiance in linear sRGB
+        let luminance = 1000.0 * zenith_luminance.max(0.0) * relative(&PEREZ_Y);
+        let x = zenith(&ZENITH_X) * relative(&PEREZ_X);
+        let y = zenith(&ZENITH_Y) * relative(&PEREZ_LOWER_Y);
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let rgb: [f32; 3] = [
+            3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+        ];
+        rgb.map(|c| c.max(0.0) / LUMINOUS_EFFICACY)
+    }
+
+    /// Radiance of the disk of the sun after the Rayleigh and aerosol
+    /// extinction of Preetham et al., zero below the horizon.
+    pub fn sun_radiance(&self) -> [f32; 3] {
+        if self.sun_direction.y() <= 0.0 {
+            return [0.0; 3];
+        }
+
+        // Relative optical mass of the air on the way
+        let theta_s = self.sun_zenith();
+        let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Wavelengths of red, green and blue in micrometers
+        [0.68f32, 0.55, 0.44].map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            SUN_LUMINANCE / LUMINOUS_EFFICACY * (-mass * (rayleigh + aerosol)).exp()
+        })
+    }
+
+    /// Bakes the sky into an equirectangular image with the sun on top.
+    pub fn to_environment(&self, width: u32, height: u32) -> Environment {
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let theta = PI * (y as f32 + 0.5) / height as f32;
+            for x in 0..width {
+                let phi = 2.0 * PI * ((x as f32 + 0.5) / width as f32 - 0.5);
+                let d = Vec3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                texels.push(self.radiance(d));
+            }
+        }
+
+        let environment = Environment::from_rgb(width, height, texels);
+        match self.sun_direction.y() > 0.0 {
+            true => environment.with_sun(Sun {
+                direction: self.sun_direction,
+                angular_radius: SUN_ANGULAR_RADIUS,
+                radiance: self.sun_radiance(),
+            }),
+            false => environment,
+        }
+    }
+}
+
+/// Direction towards the sun seen from `latitude` and `longitude` degrees
+/// (north and east positive) on `date` (year, month, day) at `time` hours of
+/// a clock `utc_offset` hours ahead of UTC, after NOAA's general solar
+/// position equations. -z is north.
+pub fn sun_direction(
+    latitude: f32,
+    longitude: f32,
+    date: (i32, u32, u32),
+    time: f32,
+    utc_offset: f32,
+) -> Vec3 {
+    let (year, month, day) = date;
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_before = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let month = month.clamp(1, 12) as usize;
+    let day_of_year = days_before[month - 1] + day + (leap && month > 2) as u32;
+    let year_days = if leap { 366.0 } else { 365.0 };
+
+    // Fractional year in radians
+    let g = 2.0 * PI / year_days * (day_of_year as f32 - 1.0 + (time - utc_offset - 12.0) / 24.0);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * g.cos()
+            - 0.032077 * g.sin()
+            - 0.014615 * (2.0 * g).cos()
+            - 0.040849 * (2.0 * g).sin());
+    let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin()
+        - 0.006758 * (2.0 * g).cos()
+        + 0.000907 * (2.0 * g).sin()
+        - 0.002697 * (3.0 * g).cos()
+        + 0.00148 * (3.0 * g).sin();
+
+    // True solar time in minutes, the hour angle is zero at solar noon
+    let solar_time = 60.0 * time + equation_of_time + 4.0 * longitude - 60.0 * utc_offset;
+    let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+    let latitude = latitude.to_radians();
+
+    let cos_zenith =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = cos_zenith.clamp(-1.0, 1.0).asin();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos())
+        + PI;
+
+    Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::luminance;
+
+    #[test]
+    fn test_sky() {
+        let sky = Sky::from_angles(45.0, 90.0, 3.0);
+        assert!((sky.sun_direction - Vec3::new(0.5f32.sqrt(), 0.5f32.sqrt(), 0.0)).length() < 1e-5);
+
+        // The zenith has the zenith luminance of the model and is blue
+        let zenith = sky.radiance(Vec3::unit_y());
+        let chi = (4.0 / 9.0 - 3.0 / 120.0) * (PI / 2.0);
+        let expected = 1000.0 * ((4.0453 * 3.0 - 4.9710) * chi.tan() - 0.2155 * 3.0 + 2.4192);
+        assert!((luminance(zenith) * LUMINOUS_EFFICACY / expected - 1.0).abs() < 0.01);
+        assert!(zenith[2] > zenith[0]);
+
+        // Brighter around the sun, black below the horizon
+        let near_sun = sky.radiance(Vec3::new(1.0, 1.1, 0.0));
+        let away = sky.radiance(Vec3::new(-1.0, 1.1, 0.0));
+        assert!(luminance(near_sun) > 2.0 * luminance(away));
+        assert_eq!(sky.radiance(-Vec3::unit_y()), [0.0; 3]);
+
+        // Redder and dimmer through more air
+        let high = sky.sun_radiance();
+        let low = Sky::from_angles(5.0, 90.0, 3.0).sun_radiance();
+        assert!(luminance(low) < luminance(high));
+        assert!(low[2] / low[0] < high[2] / high[0]);
+        assert_eq!(Sky::from_angles(-5.0, 90.0, 3.0).sun_radiance(), [0.0; 3]);
+
+        let environment = sky.to_environment(8, 4);
+        assert_eq!((environment.width, environment.height), (8, 4));
+        assert_eq!(environment.texels[31], [0.0; 3]);
+        assert_eq!(environment.sun.unwrap().radiance, high);
+        assert!(Sky::from_angles(-5.0, 0.0, 3.0)
+            .to_environment(8, 4)
+            .sun
+            .is_none());
+    }
+
+    #[test]
+    fn test_sun_direction() {
+        // Local solar noon at the June solstice, 52°N on the prime meridian
+        let d = sun_direction(52.0, 0.0, (2021, 6, 21), 12.0, 0.0);
+        let elevation = d.y().asin().to_degrees();
+        assert!((elevation - 61.4).abs() < 0.3, "{}", elevation);
+        assert!(d.z() > 0.99 * d.length() * elevation.to_radians().cos());
+
+        // Morning in the east, the same instant in another time zone
+        let morning = sun_direction(52.0, 13.4, (2021, 3, 20), 9.0, 1.0);
+        assert!(morning.x() > 0.0 && morning.y() > 0.0);
+        let utc = sun_direction(52.0, 13.4, (2021, 3, 20), 8.0, 0.0);
+        assert!((morning - utc).length() < 1e-5);
+
+        // Night
+        assert!(sun_direction(52.0, 0.0, (2021, 12, 21), 0.0, 0.0).y() < 0.0);
+    }
+}
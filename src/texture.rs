@@ -0,0 +1,312 @@
+use std::path::Path;
+
+use crate::traits::AsBytes;
+
+/// Texture index of materials without a texture.
+pub const NO_TEXTURE: u32 = 0xFFFFFFFF;
+
+/// Largest side of the grid atlas, the 3D texture size all adapters support.
+pub const MAX_ATLAS_SIZE: u32 = 2048;
+
+// Kinds in the texture table, see texture.glsl
+const KIND_IMAGE: u32 = 0;
+const KIND_IMAGE_SRGB: u32 = 1;
+const KIND_CHECKER: u32 = 2;
+const KIND_NOISE: u32 = 3;
+const KIND_MARBLE: u32 = 4;
+const KIND_GRADIENT: u32 = 5;
+const KIND_GRID: u32 = 6;
+
+/// Images are looked up by UV, procedural textures are evaluated at the
+/// world space hit point.
+#[derive(Clone, Debug)]
+pub enum Texture {
+    Image(Image),
+    /// Alternating cubes of size `1 / scale`, centered on whole multiples of
+    /// it so surfaces on whole coordinates don't straddle two cells.
+    Checker {
+        even: [f32; 3],
+        odd: [f32; 3],
+        scale: f32,
+    },
+    /// Fractal Brownian motion of Perlin noise blending from `low` to `high`.
+    Noise {
+        low: [f32; 3],
+        high: [f32; 3],
+        scale: f32,
+        octaves: u32,
+    },
+    /// Bands along z distorted by turbulence.
+    Marble {
+        low: [f32; 3],
+        high: [f32; 3],
+        scale: f32,
+        octaves: u32,
+    },
+    /// Linear blend from `from` at `start` to `to` at `end`, clamped.
+    Gradient {
+        from: [f32; 3],
+        to: [f32; 3],
+        start: [f32; 3],
+        end: [f32; 3],
+    },
+    /// Density of a heterogeneous volume, sampled in the volume's box.
+    Grid(Grid),
+}
+
+/// RGBA8 image sampled with bilinear filtering and repeat wrapping. `srgb`
+/// images are decoded to linear when sampled.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub srgb: bool,
+    /// Packed RGBA8 texels, rows from the top.
+    pub texels: Vec<u32>,
+}
+
+/// Scalar values on a regular 3D grid, trilinearly interpolated between the
+/// cell centers and clamped at the edges.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    /// `x` varies fastest, then `y`, then `z`.
+    pub values: Vec<f32>,
+}
+
+impl Grid {
+    /// Largest value, the majorant used to track through the grid.
+    pub fn max(&self) -> f32 {
+        self.values.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// All density grids of `textures` stacked along z in order and padded
+    /// with zeros to the widest and tallest, uploaded as one 3D texture. A
+    /// single zero voxel without grids.
+    pub fn atlas(textures: &[Texture]) -> Grid {
+        let [width, height, depth] = Self::atlas_size(textures);
+        let mut values = vec![0.0; (width * height * depth) as usize];
+        let mut z0 = 0;
+        for g in grids(textures) {
+            for (i, row) in g.values.chunks_exact(g.width as usize).enumerate() {
+                let (y, z) = (i as u32 % g.height, z0 + i as u32 / g.height);
+                let start = ((z * height + y) * width) as usize;
+                values[start..start + row.len()].copy_from_slice(row);
+            }
+            z0 += g.depth;
+        }
+
+        Grid {
+            width,
+            height,
+            depth,
+            values,
+        }
+    }
+
+    /// Width, height and depth of `atlas`, each at most `MAX_ATLAS_SIZE` for
+    /// the atlas to be uploaded.
+    pub fn atlas_size(textures: &[Texture]) -> [u32; 3] {
+        [
+            grids(textures).map(|g| g.width).max().unwrap_or(1),
+            grids(textures).map(|g| g.height).max().unwrap_or(1),
+            grids(textures).map(|g| g.depth).sum::<u32>().max(1),
+        ]
+    }
+}
+
+fn grids(textures: &[Texture]) -> impl Iterator<Item = &Grid> {
+    textures.iter().filter_map(|t| match t {
+        Texture::Grid(g) => Some(g),
+        _ => None,
+    })
+}
+
+impl Texture {
+    pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgba();
+        let (width, height) = image.dimensions();
+        Ok(Self::from_rgba8(width, height, &image.into_raw(), srgb))
+    }
+
+    pub fn from_rgba8(width: u32, height: u32, rgba: &[u8], srgb: bool) -> Self {
+        Texture::Image(Image {
+            width,
+            height,
+            srgb,
+            texels: rgba
+                .chunks_exact(4)
+                .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+                .collect(),
+        })
+    }
+
+    /// Kind, width and height in the texture table.
+    fn header(&self) -> [u32; 3] {
+        match self {
+            Texture::Image(i) if i.srgb => [KIND_IMAGE_SRGB, i.width, i.height],
+            Texture::Image(i) => [KIND_IMAGE, i.width, i.height],
+            Texture::Checker { .. } => [KIND_CHECKER, 0, 0],
+            Texture::Noise { .. } => [KIND_NOISE, 0, 0],
+            Texture::Marble { .. } => [KIND_MARBLE, 0, 0],
+            Texture::Gradient { .. } => [KIND_GRADIENT, 0, 0],
+            Texture::Grid(g) => [KIND_GRID, g.width, g.height],
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        match self {
+            Texture::Image(i) => i.texels.len(),
+            Texture::Grid(_) => 3,
+            _ => self.params().len(),
+        }
+    }
+
+    /// Parameters of procedural textures as they are laid out in the buffer.
+    fn params(&self) -> Vec<u32> {
+        let floats = |f: &[f32]| f.iter().map(|f| f.to_bits()).collect::<Vec<u32>>();
+        match self {
+            Texture::Image(_) | Texture::Grid(_) => Vec::new(),
+            Texture::Checker { even, odd, scale } => {
+                floats(&[even[0], even[1], even[2], odd[0], odd[1], odd[2], *scale])
+            }
+            Texture::Noise {
+                low,
+                high,
+                scale,
+                octaves,
+            }
+            | Texture::Marble {
+                low,
+                high,
+                scale,
+                octaves,
+            } => {
+                let mut data = floats(&[low[0], low[1], low[2], high[0], high[1], high[2], *scale]);
+                data.push(*octaves);
+                data
+            }
+            Texture::Gradient {
+                from,
+                to,
+                start,
+                end,
+            } => floats(&[
+                from[0], from[1], from[2], to[0], to[1], to[2], start[0], start[1], start[2],
+                end[0], end[1], end[2],
+            ]),
+        }
+    }
+}
+
+// One u32 array: a header with the texture count, then per texture its kind,
+// the word offset of its data, width and height, then the data of all
+// textures. The data of grids is their depth, their first slice in the grid
+// atlas and their largest value, the values themselves are in the atlas.
+impl AsBytes for Vec<Texture> {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut words: Vec<u32> = Vec::with_capacity(self.bytes_size() / 4);
+        words.extend_from_slice(&[self.len() as u32, 0, 0, 0]);
+
+        let mut offset = 4 + 4 * self.len();
+        let mut z0 = 0;
+        for t in self.iter() {
+            let [kind, width, height] = t.header();
+            words.extend_from_slice(&[kind, offset as u32, width, height]);
+            offset += t.data_len();
+        }
+        for t in self.iter() {
+            match t {
+                Texture::Image(i) => words.extend_from_slice(&i.texels),
+                Texture::Grid(g) => {
+                    words.extend_from_slice(&[g.depth, z0, g.max().to_bits()]);
+                    z0 += g.depth;
+                }
+                _ => words.extend(t.params()),
+            }
+        }
+
+        bytemuck::cast_slice(&words).to_vec()
+    }
+
+    fn bytes_size(&self) -> usize {
+        4 * (4 + 4 * self.len() + self.iter().map(|t| t.data_len()).sum::<usize>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_bytes() {
+        let textures = vec![
+            Texture::from_rgba8(1, 1, &[255, 0, 0, 255], true),
+            Texture::from_rgba8(2, 1, &[0, 255, 0, 255, 0, 0, 255, 0], false),
+            Texture::Checker {
+                even: [1.0; 3],
+                odd: [0.0; 3],
+                scale: 2.0,
+            },
+            Texture::Grid(Grid {
+                width: 1,
+                height: 1,
+                depth: 2,
+                values: vec![0.5, 2.0],
+            }),
+        ];
+
+        let bytes = textures.as_bytes();
+        assert_eq!(bytes.len(), textures.bytes_size());
+
+        let words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        assert_eq!(words[0], 4);
+        assert_eq!(&words[4..8], &[KIND_IMAGE_SRGB, 20, 1, 1]);
+        assert_eq!(&words[8..12], &[KIND_IMAGE, 21, 2, 1]);
+        assert_eq!(&words[12..16], &[KIND_CHECKER, 23, 0, 0]);
+        assert_eq!(&words[16..20], &[KIND_GRID, 30, 1, 1]);
+        assert_eq!(words[20], 0xFF0000FF);
+        assert_eq!(&words[21..23], &[0xFF00FF00, 0x00FF0000]);
+        assert_eq!(f32::from_bits(words[23]), 1.0);
+        assert_eq!(f32::from_bits(words[29]), 2.0);
+        assert_eq!(&words[30..32], &[2, 0]);
+        assert_eq!(f32::from_bits(words[32]), 2.0);
+        assert_eq!(words.len(), 33);
+    }
+
+    #[test]
+    fn test_atlas() {
+        let grid = |width, height, depth| {
+            let values = (0..width * height * depth)
+                .map(|v| v as f32 + 1.0)
+                .collect();
+            Texture::Grid(Grid {
+                width,
+                height,
+                depth,
+                values,
+            })
+        };
+
+        let atlas = Grid::atlas(&[
+            grid(2, 1, 2),
+            Texture::from_rgba8(1, 1, &[0; 4], false),
+            grid(1, 2, 1),
+        ]);
+        assert_eq!((atlas.width, atlas.height, atlas.depth), (2, 2, 3));
+        assert_eq!(&atlas.values[0..4], &[1.0, 2.0, 0.0, 0.0]);
+        assert_eq!(&atlas.values[4..8], &[3.0, 4.0, 0.0, 0.0]);
+        assert_eq!(&atlas.values[8..12], &[1.0, 0.0, 2.0, 0.0]);
+
+        assert_eq!(Grid::atlas_size(&[grid(2, 1, 2), grid(1, 2, 1)]), [2, 2, 3]);
+
+        let empty = Grid::atlas(&[]);
+        assert_eq!((empty.width, empty.height, empty.depth), (1, 1, 1));
+        assert_eq!(empty.values, vec![0.0]);
+    }
+}